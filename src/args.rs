@@ -1,4 +1,6 @@
 use std::fmt::Display;
+use std::num::NonZeroUsize;
+use std::thread;
 
 use clap::{Parser, ValueEnum};
 
@@ -46,4 +48,31 @@ pub struct Args {
     /// The mode to build the site in
     #[arg(value_enum, short, long, default_value_t = BuildMode::Development)]
     pub mode: BuildMode,
+
+    /// Override `site.base_url` from the config file, e.g. for preview deployments
+    #[arg(long)]
+    pub base_url: Option<String>,
+
+    /// With `clean`, also remove the build cache
+    #[arg(long)]
+    pub purge_cache: bool,
+
+    /// The maximum number of files to compile at once. Defaults to the number
+    /// of CPU cores
+    #[arg(short, long)]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Keep compiling other files after one fails, and report every failure
+    /// at the end
+    #[arg(short, long)]
+    pub keep_going: bool,
+}
+
+impl Args {
+    pub fn jobs(&self) -> usize {
+        self.jobs
+            .or_else(|| thread::available_parallelism().ok())
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+    }
 }
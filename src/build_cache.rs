@@ -0,0 +1,288 @@
+use crate::args::BuildMode;
+use crate::compilers::AssetMap;
+use crate::config::Config;
+use crate::deps::normalize;
+use crate::error::{Error, Result};
+use crate::post_cache::Post;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::fs;
+
+/// Where the cache is kept, relative to the working directory
+pub const CACHE_DIR: &str = ".sitegen-cache";
+
+const CACHE_FILE: &str = "cache.json";
+
+/// Remembers the hashes of the inputs each output was built from, across
+/// invocations, so that outputs whose inputs didn't change can be skipped.
+/// Copied files are compared by size and modification time instead, so that
+/// unchanged ones aren't read at all.
+///
+/// Every entry also covers the config and build mode, and posts additionally
+/// cover every partial and the asset map, so changing any of those invalidates
+/// the entries that depend on them.
+pub struct BuildCache {
+    fingerprint: String,
+    /// A digest of the asset map, whose fingerprinted URLs posts link to
+    assets: Mutex<String>,
+    templates: Mutex<BTreeMap<String, String>>,
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct CacheEntries {
+    fingerprint: String,
+    posts: BTreeMap<PathBuf, CachedPost>,
+    stylesheets: BTreeMap<PathBuf, CachedStylesheet>,
+    files: BTreeMap<PathBuf, CachedFile>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedPost {
+    hash: String,
+    output: PathBuf,
+    post: Post,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedStylesheet {
+    hash: String,
+    output: PathBuf,
+    dependencies: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedFile {
+    source: PathBuf,
+    output: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl BuildCache {
+    /// Loads the cache left by a previous build. A missing or unreadable cache
+    /// is treated as empty
+    pub async fn load(config: &Config, build_mode: BuildMode) -> Self {
+        let fingerprint = digest([build_mode.to_string(), format!("{:?}", config)]);
+
+        let path = Path::new(CACHE_DIR).join(CACHE_FILE);
+        let mut entries = match fs::read(path.as_path()).await {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                log::warn!("ignoring unreadable build cache: {}", e);
+                CacheEntries::default()
+            }),
+            Err(_) => CacheEntries::default(),
+        };
+
+        if entries.fingerprint != fingerprint {
+            log::debug!("config or build mode changed, discarding build cache");
+            entries = CacheEntries {
+                fingerprint: fingerprint.clone(),
+                ..Default::default()
+            };
+        }
+
+        Self {
+            fingerprint,
+            assets: Mutex::new(String::new()),
+            templates: Mutex::new(BTreeMap::new()),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Writes the cache to disk, dropping entries for sources that no longer
+    /// exist
+    pub async fn save(&self) -> Result<()> {
+        let contents = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.posts.retain(|source, _| source.exists());
+            entries.stylesheets.retain(|source, _| source.exists());
+            entries.files.retain(|_, file| file.source.exists());
+            serde_json::to_vec(&*entries).map_err(|e| Error::Io(CACHE_FILE.into(), e.into()))?
+        };
+
+        fs::create_dir_all(CACHE_DIR)
+            .await
+            .map_err(Error::io(CACHE_DIR))?;
+        let path = Path::new(CACHE_DIR).join(CACHE_FILE);
+        fs::write(path.as_path(), contents)
+            .await
+            .map_err(Error::io(&path))
+    }
+
+    /// Removes the cache directory
+    pub async fn purge() -> Result<()> {
+        match fs::remove_dir_all(CACHE_DIR).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::Io(CACHE_DIR.into(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn add_template(&self, name: &str, source: &str) {
+        self.templates
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), digest([source]));
+    }
+
+    pub fn remove_template(&self, name: &str) {
+        self.templates.lock().unwrap().remove(name);
+    }
+
+    pub fn clear_templates(&self) {
+        self.templates.lock().unwrap().clear();
+    }
+
+    /// Sets the asset map posts are rendered with
+    pub fn set_assets(&self, assets: &AssetMap) {
+        *self.assets.lock().unwrap() = assets.digest();
+    }
+
+    /// The hash a post rendered from `content` is cached under
+    pub fn post_hash(&self, content: &str) -> String {
+        let assets = self.assets.lock().unwrap();
+        let templates = self.templates.lock().unwrap();
+        let templates = templates
+            .iter()
+            .flat_map(|(name, hash)| [name.as_str(), hash.as_str()]);
+
+        digest(
+            [self.fingerprint.as_str(), assets.as_str(), content]
+                .into_iter()
+                .chain(templates),
+        )
+    }
+
+    /// The post previously rendered from `source`, if its hash matches and its
+    /// output still exists
+    pub fn post(&self, source: &Path, hash: &str) -> Option<Post> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.posts.get(&normalize(source))?;
+
+        (cached.hash == hash && cached.output.exists()).then(|| cached.post.clone())
+    }
+
+    pub fn insert_post(&self, hash: String, output: &Path, post: &Post) {
+        let cached = CachedPost {
+            hash,
+            output: output.to_path_buf(),
+            post: post.clone(),
+        };
+
+        self.entries
+            .lock()
+            .unwrap()
+            .posts
+            .insert(normalize(&post.source), cached);
+    }
+
+    /// The output and dependencies of a stylesheet previously compiled from
+    /// `entry`, if none of its dependencies changed and its output still exists
+    pub async fn stylesheet(&self, entry: &Path) -> Option<(PathBuf, Vec<PathBuf>)> {
+        let (hash, output, dependencies) = {
+            let entries = self.entries.lock().unwrap();
+            let cached = entries.stylesheets.get(&normalize(entry))?;
+            (
+                cached.hash.clone(),
+                cached.output.clone(),
+                cached.dependencies.clone(),
+            )
+        };
+
+        if !output.exists() || hash_files(&dependencies).await? != hash {
+            return None;
+        }
+
+        Some((output, dependencies))
+    }
+
+    pub async fn insert_stylesheet(&self, entry: &Path, output: &Path, dependencies: &[PathBuf]) {
+        let Some(hash) = hash_files(dependencies).await else {
+            return;
+        };
+
+        let cached = CachedStylesheet {
+            hash,
+            output: output.to_path_buf(),
+            dependencies: dependencies.to_vec(),
+        };
+
+        self.entries
+            .lock()
+            .unwrap()
+            .stylesheets
+            .insert(normalize(entry), cached);
+    }
+
+    /// Where `from` was last copied to in place of `logical`, if that still
+    /// exists and `from` has the same size and modification time
+    pub fn copied_file(&self, from: &Path, logical: &Path, metadata: &Metadata) -> Option<PathBuf> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.files.get(&normalize(logical))?;
+
+        let unchanged = cached.source == normalize(from)
+            && cached.size == metadata.len()
+            && cached.modified.is_some()
+            && cached.modified == metadata.modified().ok();
+
+        (unchanged && cached.output.exists()).then(|| cached.output.clone())
+    }
+
+    pub fn insert_file(&self, from: &Path, logical: &Path, output: &Path, metadata: &Metadata) {
+        let cached = CachedFile {
+            source: normalize(from),
+            output: output.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        };
+
+        self.entries
+            .lock()
+            .unwrap()
+            .files
+            .insert(normalize(logical), cached);
+    }
+}
+
+/// Hashes the paths and contents of a set of files, or `None` if any of them
+/// can no longer be read
+async fn hash_files(paths: &[PathBuf]) -> Option<String> {
+    let mut parts = Vec::new();
+    for path in paths {
+        parts.push(path.to_string_lossy().as_bytes().to_vec());
+        parts.push(fs::read(path).await.ok()?);
+    }
+
+    Some(digest(parts))
+}
+
+/// Hex-encoded SHA-256 of a sequence of parts. Each part is length-prefixed so
+/// that moving bytes between parts changes the hash
+pub fn digest<I, T>(parts: I) -> String
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut hasher = Sha256::new();
+    for part in parts {
+        let part = part.as_ref();
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
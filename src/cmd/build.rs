@@ -1,55 +1,734 @@
 use crate::args::Args;
+use crate::build_cache::BuildCache;
 use crate::compilers::{
-    CompilerOptions, FileCopier, HandlebarsCompiler, MarkdownCompiler, SassCompiler,
+    check_unique_outputs, glob_paths, output_path, Asset, AssetMap, CompiledStylesheet,
+    CompilerOptions, FeedGenerator, FileCopier, HandlebarsCompiler, MarkdownCompiler, PageMeta,
+    SassCompiler, SitemapGenerator, TaxonomyCompiler, ASSET_MANIFEST_FILE,
 };
-use crate::config::Config;
+use crate::config::{Config, CONFIG_FILE};
+use crate::deps::{normalize, DependencyGraph, RebuildPlan};
 use crate::error::{Error, Result};
-use std::path::Path;
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::parallel::Jobs;
+use crate::post_cache::PostCache;
+use crate::staging::Staging;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use tokio::fs;
 
-pub async fn build(args: &Args, config: &Config) -> Result<()> {
-    fs::create_dir_all(config.build.out_dir.as_str())
-        .await
-        .map_err(Error::Io)?;
-
-    let meta_path = Path::new(config.build.out_dir.as_str()).join("sitegen_meta.toml");
-    fs::write(meta_path.as_path(), format!("mode = {}\n", args.mode))
-        .await
-        .map_err(Error::Io)?;
-
-    let sass_opts = CompilerOptions {
-        input_pattern: config.build.style_pattern.as_str(),
-        output_path: config.build.out_dir.as_str(),
-    };
-    let sass_compiler = SassCompiler::new(sass_opts);
-    sass_compiler.compile().await?;
-
-    let mut handlebars = HandlebarsCompiler::new(args.mode);
-    handlebars
-        .add_partials(config.build.partials_pattern.as_str())
-        .await?;
-
-    let markdown = MarkdownCompiler::new(args.mode);
-    let post_cache = markdown
-        .compile(
-            config.build.post_pattern.as_str(),
-            config.build.out_dir.as_str(),
-            &handlebars,
+/// What a build wrote into `out_dir`
+pub struct BuildOutput {
+    /// Every HTML page
+    pub pages: Vec<PathBuf>,
+    /// Stylesheets whose contents changed
+    pub stylesheets: Vec<PathBuf>,
+}
+
+pub async fn build(args: &Args, config: &Config) -> Result<BuildOutput> {
+    Builder::new(args, config).await.build().await
+}
+
+/// Builds the site and keeps enough state around to rebuild only what a change
+/// affects
+pub struct Builder {
+    args: Args,
+    /// Where stylesheets and copied files were written
+    assets: AssetMap,
+    cache: BuildCache,
+    config: Config,
+    /// Whether the last build failed, leaving state that doesn't match the
+    /// published output
+    failed: bool,
+    graph: DependencyGraph,
+    handlebars: HandlebarsCompiler<'static>,
+    jobs: Jobs,
+    manifest: Manifest,
+    pages: BTreeMap<PathBuf, PageMeta>,
+    post_cache: PostCache,
+    staging: Staging,
+}
+
+impl Builder {
+    pub async fn new(args: &Args, config: &Config) -> Self {
+        Self {
+            args: args.clone(),
+            assets: AssetMap::default(),
+            cache: BuildCache::load(config, args.mode).await,
+            config: config.clone(),
+            failed: false,
+            graph: DependencyGraph::new(),
+            handlebars: Self::handlebars(args, config),
+            jobs: Jobs::new(args.jobs(), args.keep_going),
+            manifest: Manifest::new(args.mode),
+            pages: BTreeMap::new(),
+            post_cache: PostCache::new(),
+            staging: Staging::new(&config.build),
+        }
+    }
+
+    fn handlebars(args: &Args, config: &Config) -> HandlebarsCompiler<'static> {
+        let mut handlebars = HandlebarsCompiler::new(args.mode, &config.site_context());
+        handlebars.enable_live_reload(config.websocket_port());
+        if let Some(options) = config.html_minify(args.mode) {
+            handlebars.enable_minify(options);
+        }
+        handlebars
+    }
+
+    /// Where outputs are written. Builds are staged, so this is only moved to
+    /// `build.out_dir` once the build succeeds
+    fn out_dir(&self) -> &str {
+        self.staging.dir()
+    }
+
+    /// Builds everything, then removes the outputs of the previous build that
+    /// are no longer produced
+    pub async fn build(&mut self) -> Result<BuildOutput> {
+        self.staging.prepare().await?;
+        let out_dir = PathBuf::from(self.out_dir());
+        let previous = Manifest::load(out_dir.as_path()).await;
+        self.manifest = Manifest::new(self.args.mode);
+
+        let result = match self.build_all().await {
+            Ok(output) => self
+                .manifest
+                .prune(out_dir.as_path(), &previous)
+                .await
+                .map(|_| output),
+            Err(error) => Err(error),
+        };
+
+        self.finish(result).await
+    }
+
+    async fn build_all(&mut self) -> Result<BuildOutput> {
+        self.jobs.take_errors();
+        self.graph = DependencyGraph::new();
+        self.handlebars = Self::handlebars(&self.args, &self.config);
+        self.pages.clear();
+        self.assets = AssetMap::default();
+        self.cache.clear_templates();
+
+        fs::create_dir_all(self.out_dir())
+            .await
+            .map_err(Error::io(self.out_dir()))?;
+        self.check_outputs().await?;
+
+        let sass_compiler = self.sass_compiler();
+        let compiled = sass_compiler.compile(&self.jobs).await?;
+        let stylesheets = self.record_stylesheets(compiled);
+        let copied = self.copy_files().await?;
+        self.record_assets(copied);
+        self.publish_assets().await?;
+
+        for partial in glob_paths(self.config.build.partials_pattern.as_str())? {
+            let added = self.add_partial(partial.as_path()).await;
+            self.jobs.collect(added)?;
+        }
+
+        let markdown = MarkdownCompiler::new(self.args.mode, self.config.taxonomies(), &self.cache);
+        self.post_cache = markdown
+            .compile(
+                self.config.build.post_pattern.as_str(),
+                self.out_dir(),
+                &self.handlebars,
+                &self.jobs,
+            )
+            .await?;
+
+        let (sort_by, order) = self.config.post_sort();
+        self.post_cache.sort(sort_by, order);
+
+        let feeds = self.jobs.collect(self.generate_feeds().await)?;
+        self.record_outputs(feeds.into_iter().flatten());
+        self.jobs.collect(self.compile_taxonomies().await)?;
+
+        let pages = glob_paths(self.config.build.page_pattern.as_str())?;
+        self.compile_pages(pages).await?;
+
+        let pages = self.finish_pages().await?;
+        self.check_errors()?;
+
+        self.log_complete("Build");
+
+        Ok(BuildOutput { pages, stylesheets })
+    }
+
+    /// Rebuilds the outputs affected by a set of changed files, falling back to
+    /// a full build for changes the dependency graph can't place. Outputs that
+    /// are no longer produced are left for the next full build to remove
+    pub async fn rebuild(&mut self, changed: &[PathBuf]) -> Result<BuildOutput> {
+        self.jobs.take_errors();
+        let config_file = normalize(Path::new(CONFIG_FILE));
+        if changed.iter().any(|path| normalize(path) == config_file) {
+            self.reload_config().await?;
+        }
+
+        let plan = self.graph.plan(&self.config, changed);
+        log::debug!("{:?}", plan);
+
+        // Fingerprinted names change with contents, so every template using
+        // `asset` would need rendering again
+        let copies_fingerprinted = self.config.build.fingerprint.is_some();
+        let assets_changed = self.fingerprint()
+            && (!plan.stylesheets.is_empty() || (plan.copy && copies_fingerprinted));
+
+        if plan.full || self.failed || assets_changed {
+            return self.build().await;
+        }
+
+        self.staging.prepare().await?;
+        let result = self.rebuild_affected(plan).await;
+
+        self.finish(result).await
+    }
+
+    /// Loads the config again after it changed. `out_dir` is kept, since
+    /// `serve` is serving it, and neither do the ports and watch paths `serve`
+    /// was started with change
+    async fn reload_config(&mut self) -> Result<()> {
+        let mut config = Config::load(CONFIG_FILE)?;
+        if let Some(base_url) = self.args.base_url.as_deref() {
+            config.override_base_url(base_url)?;
+        }
+        config.build.out_dir = self.config.build.out_dir.clone();
+
+        self.cache = BuildCache::load(&config, self.args.mode).await;
+        self.staging = Staging::new(&config.build);
+        self.config = config;
+
+        Ok(())
+    }
+
+    /// Publishes the staged output of a successful build. A failed build
+    /// leaves `out_dir` as it was, and since the state kept for rebuilding no
+    /// longer matches it, the next rebuild is a full one
+    async fn finish(&mut self, result: Result<BuildOutput>) -> Result<BuildOutput> {
+        let result = match result {
+            Ok(output) => self.publish(output).await,
+            Err(error) => Err(error),
+        };
+
+        self.failed = result.is_err();
+        if self.failed {
+            self.staging.discard().await;
+            self.cache = BuildCache::load(&self.config, self.args.mode).await;
+        }
+
+        result
+    }
+
+    async fn publish(&self, output: BuildOutput) -> Result<BuildOutput> {
+        // Recorded only once the output they describe is in place, so that a
+        // failed swap can't leave the cache skipping posts `out_dir` lacks
+        self.staging.publish().await?;
+        let out_dir = self.staging.published(Path::new(self.out_dir()));
+        self.manifest.save(out_dir.as_path()).await?;
+        self.cache.save().await?;
+
+        let published = |paths: Vec<PathBuf>| {
+            paths
+                .iter()
+                .map(|path| self.staging.published(path))
+                .collect()
+        };
+
+        Ok(BuildOutput {
+            pages: published(output.pages),
+            stylesheets: published(output.stylesheets),
+        })
+    }
+
+    async fn rebuild_affected(&mut self, plan: RebuildPlan) -> Result<BuildOutput> {
+        self.check_outputs().await?;
+
+        let sass_compiler = self.sass_compiler();
+        let (stylesheets, removed): (Vec<_>, Vec<_>) =
+            plan.stylesheets.iter().partition(|path| path.exists());
+        let compiled = self
+            .jobs
+            .try_map(stylesheets, |stylesheet| {
+                sass_compiler.compile_stylesheet(stylesheet)
+            })
+            .await?;
+        for stylesheet in removed {
+            self.graph.remove_stylesheet(stylesheet);
+        }
+        let stylesheets = self.record_stylesheets(compiled);
+
+        if plan.copy {
+            let copied = self.copy_files().await?;
+            self.record_assets(copied);
+        }
+
+        for partial in &plan.partials {
+            if partial.exists() {
+                let added = self.add_partial(partial).await;
+                self.jobs.collect(added)?;
+            } else if let Some(name) = self.graph.remove_partial(partial) {
+                self.handlebars.remove_partial(name.as_str());
+                self.cache.remove_template(name.as_str());
+            }
+        }
+
+        self.rebuild_posts(&plan).await?;
+
+        let taxonomy_templates_changed = self.config.taxonomies().iter().any(|taxonomy| {
+            plan.uses_template(&self.graph, taxonomy.template.as_str())
+                || taxonomy
+                    .index_template
+                    .as_deref()
+                    .map(|template| plan.uses_template(&self.graph, template))
+                    .unwrap_or_default()
+        });
+
+        if plan.listings {
+            let feeds = self.jobs.collect(self.generate_feeds().await)?;
+            self.record_outputs(feeds.into_iter().flatten());
+        }
+
+        if plan.listings || taxonomy_templates_changed {
+            self.jobs.collect(self.compile_taxonomies().await)?;
+        }
+
+        let (pages, removed): (Vec<_>, Vec<_>) =
+            plan.pages.into_iter().partition(|page| page.exists());
+        for page in removed {
+            self.graph.remove_page(page);
+        }
+        self.compile_pages(pages).await?;
+
+        let pages = self.finish_pages().await?;
+        self.check_errors()?;
+
+        self.log_complete("Rebuild");
+
+        Ok(BuildOutput { pages, stylesheets })
+    }
+
+    async fn rebuild_posts(&mut self, plan: &RebuildPlan) -> Result<()> {
+        let mut posts = plan.posts.clone();
+        for post in self.post_cache.posts() {
+            let template = post
+                .front_matter
+                .get("template")
+                .and_then(|template| template.as_str())
+                .unwrap_or_default();
+
+            if plan.uses_template(&self.graph, template) {
+                posts.insert(post.source.clone());
+            }
+        }
+
+        if posts.is_empty() {
+            return Ok(());
+        }
+
+        for source in &posts {
+            self.post_cache.remove(source);
+        }
+
+        let markdown = MarkdownCompiler::new(self.args.mode, self.config.taxonomies(), &self.cache);
+        let sources = posts.into_iter().filter(|source| source.exists()).collect();
+        let (markdown, handlebars) = (&markdown, &self.handlebars);
+        let out_dir = Path::new(self.out_dir());
+        let rendered = self
+            .jobs
+            .try_map(sources, |source| async move {
+                markdown
+                    .render_post(source.as_path(), handlebars, out_dir)
+                    .await
+            })
+            .await?;
+
+        for post in rendered.into_iter().flatten() {
+            self.post_cache.replace(post);
+        }
+
+        let (sort_by, order) = self.config.post_sort();
+        self.post_cache.sort(sort_by, order);
+
+        Ok(())
+    }
+
+    /// Fails if two inputs would be written to the same output, before any of
+    /// them are. Generated files are attributed to the config
+    async fn check_outputs(&self) -> Result<()> {
+        let out_dir = Path::new(self.out_dir());
+        let config = PathBuf::from(CONFIG_FILE);
+        let mut outputs = self.sass_compiler().outputs()?;
+        outputs.extend(self.file_copier().files().await?);
+
+        // Posts and pages that can't be read fail again when they're rendered,
+        // which reports them like every other error
+        let markdown = MarkdownCompiler::new(self.args.mode, self.config.taxonomies(), &self.cache);
+        let markdown = &markdown;
+        let sources = glob_paths(self.config.build.post_pattern.as_str())?;
+        let posts = self
+            .jobs
+            .try_map(sources, |source| async move {
+                Ok(markdown.plan_post(source.as_path(), out_dir).await.ok())
+            })
+            .await?
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>();
+        outputs.extend(
+            posts
+                .iter()
+                .map(|post| (post.source.clone(), post.output.clone())),
+        );
+
+        let taxonomies = TaxonomyCompiler::new(self.args.mode, self.config.taxonomies());
+        for output in taxonomies.outputs(out_dir, &posts)? {
+            outputs.push((config.clone(), output));
+        }
+
+        let pattern = self.config.build.page_pattern.as_str();
+        for page in glob_paths(pattern)? {
+            let Ok(contents) = fs::read_to_string(page.as_path()).await else {
+                continue;
+            };
+            let path = output_path(pattern, page.as_path(), out_dir, "html");
+            let page_outputs = self.handlebars.page_outputs(
+                page.as_path(),
+                contents.as_str(),
+                out_dir,
+                path,
+                posts.len(),
+            );
+            for output in page_outputs.unwrap_or_default() {
+                outputs.push((page.clone(), output));
+            }
+        }
+
+        let mut generated = vec![out_dir.join(MANIFEST_FILE)];
+        if self.fingerprint() {
+            generated.push(out_dir.join(ASSET_MANIFEST_FILE));
+        }
+        if let Some(site) = &self.config.site {
+            if let Some(feeds) = &self.config.feeds {
+                generated.extend(FeedGenerator::new(site, feeds).outputs(out_dir));
+            }
+            generated.extend(SitemapGenerator::new(site).outputs(out_dir));
+        }
+        outputs.extend(generated.into_iter().map(|output| (config.clone(), output)));
+
+        check_unique_outputs(
+            out_dir,
+            outputs
+                .iter()
+                .map(|(input, output)| (input.as_path(), output.as_path())),
         )
-        .await?;
+    }
+
+    fn log_complete(&self, kind: &str) {
+        match self.handlebars.take_bytes_saved() {
+            0 => log::info!("{} complete", kind),
+            saved => log::info!("{} complete, minifying HTML saved {} bytes", kind, saved),
+        }
+    }
+
+    /// Fails with every error collected during the build, if there were any
+    fn check_errors(&self) -> Result<()> {
+        let errors = self.jobs.take_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Build(errors))
+        }
+    }
+
+    fn sass_compiler(&self) -> SassCompiler<'_> {
+        let sass_opts = CompilerOptions {
+            input_pattern: self.config.build.style_pattern.as_str(),
+            entry_points: self.config.build.style_entries.as_ref(),
+            load_paths: self.config.build.style_load_paths.as_ref(),
+            style: self.config.css_style(self.args.mode),
+            source_maps: self.config.source_maps(self.args.mode),
+            output_path: self.out_dir(),
+            fingerprint: self.fingerprint(),
+            cache: &self.cache,
+        };
+
+        SassCompiler::new(sass_opts)
+    }
+
+    /// Records the imports of compiled stylesheets, returning the outputs that
+    /// changed
+    fn record_stylesheets(&mut self, compiled: Vec<CompiledStylesheet>) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for stylesheet in compiled {
+            self.record_outputs(&stylesheet.source_map);
+            self.record_assets([Asset {
+                logical: stylesheet.logical,
+                output: stylesheet.output.clone(),
+            }]);
+
+            if let Some(entry) = stylesheet.dependencies.first() {
+                self.graph.add_stylesheet(entry, &stylesheet.dependencies);
+            }
+
+            if stylesheet.changed {
+                changed.push(stylesheet.output);
+            }
+        }
+
+        changed
+    }
+
+    /// Adds stylesheets and copied files to the manifest and the asset map
+    fn record_assets(&mut self, assets: impl IntoIterator<Item = Asset>) {
+        let out_dir = PathBuf::from(self.out_dir());
+        for asset in assets {
+            self.record_outputs([&asset.output]);
+            self.assets.insert(out_dir.as_path(), &asset);
+        }
+    }
+
+    /// Makes the asset map available to the `asset` helper, and when assets
+    /// are fingerprinted writes it to `out_dir` for deployment
+    async fn publish_assets(&mut self) -> Result<()> {
+        self.handlebars.set_assets(self.assets.clone());
+        self.cache.set_assets(&self.assets);
+
+        if self.fingerprint() {
+            let written = self.assets.write(Path::new(self.out_dir())).await?;
+            self.record_outputs([written]);
+        }
+
+        Ok(())
+    }
 
-    handlebars
-        .compile_all(
-            config.build.page_pattern.as_str(),
-            config.build.out_dir.as_str(),
-            &post_cache,
+    /// Whether stylesheets, and copied files matching `build.fingerprint`, get
+    /// content hashes in their names
+    fn fingerprint(&self) -> bool {
+        self.args.mode.is_release()
+    }
+
+    async fn add_partial(&mut self, partial: &Path) -> Result<()> {
+        let (name, source) = self.handlebars.add_partial(partial).await?;
+        self.graph
+            .add_partial(partial, name.as_str(), source.as_str());
+        self.cache.add_template(name.as_str(), source.as_str());
+        Ok(())
+    }
+
+    async fn compile_pages(&mut self, pages: Vec<PathBuf>) -> Result<()> {
+        let mut sources = Vec::new();
+        for page in pages {
+            let contents = fs::read_to_string(page.as_path())
+                .await
+                .map_err(Error::io(&page))?;
+            self.graph.add_page(page.as_path(), contents.as_str());
+            sources.push((page, contents));
+        }
+
+        let out_dir = Path::new(self.out_dir());
+        let pattern = self.config.build.page_pattern.as_str();
+        let sources = sources
+            .into_iter()
+            .map(|(page, contents)| {
+                let output = output_path(pattern, page.as_path(), out_dir, "html");
+                (page, contents, output)
+            })
+            .collect::<Vec<_>>();
+
+        let (handlebars, post_cache) = (&self.handlebars, &self.post_cache);
+        self.jobs
+            .try_map(sources, |(page, contents, output)| async move {
+                handlebars
+                    .compile_page(
+                        page.as_path(),
+                        contents.as_str(),
+                        out_dir,
+                        output,
+                        post_cache,
+                    )
+                    .await
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn generate_feeds(&self) -> Result<Vec<PathBuf>> {
+        let Some(feeds) = &self.config.feeds else {
+            return Ok(Vec::new());
+        };
+
+        let site = self
+            .config
+            .site
+            .as_ref()
+            .ok_or(Error::MissingConfig("site"))?;
+        let feed_generator = FeedGenerator::new(site, feeds);
+        feed_generator
+            .generate(self.out_dir(), &self.post_cache)
+            .await
+    }
+
+    async fn compile_taxonomies(&self) -> Result<()> {
+        let taxonomies = TaxonomyCompiler::new(self.args.mode, self.config.taxonomies());
+        taxonomies
+            .compile(self.out_dir(), &self.post_cache, &self.handlebars)
+            .await
+    }
+
+    /// Collects the pages written since the last call and regenerates the
+    /// sitemap if any were
+    async fn finish_pages(&mut self) -> Result<Vec<PathBuf>> {
+        let written = self.handlebars.take_written_pages();
+        if written.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut paths = written
+            .iter()
+            .map(|page| page.path.clone())
+            .collect::<Vec<_>>();
+        paths.sort();
+        for page in written {
+            self.pages.insert(page.path, page.meta);
+        }
+        self.record_outputs(&paths);
+
+        if let Some(site) = &self.config.site {
+            let sitemap = SitemapGenerator::new(site);
+            let written = sitemap.generate(self.out_dir(), &self.pages).await?;
+            self.record_outputs(written);
+        }
+
+        Ok(paths)
+    }
+
+    /// Adds files written to `out_dir` to the manifest
+    fn record_outputs<I, P>(&mut self, paths: I)
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let out_dir = Path::new(self.staging.dir());
+        self.manifest.insert(out_dir, paths);
+    }
+
+    async fn copy_files(&self) -> Result<Vec<Asset>> {
+        self.file_copier().copy(&self.jobs).await
+    }
+
+    fn file_copier(&self) -> FileCopier<'_> {
+        FileCopier::new(
+            self.config.build.copy.as_ref(),
+            self.config.build.copy_exclude.as_ref(),
+            self.out_dir(),
+            self.config
+                .build
+                .fingerprint
+                .as_ref()
+                .filter(|_| self.fingerprint()),
+            &self.cache,
         )
-        .await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::env;
+
+    /// A temporary directory holding `files`, and the config of a site in it
+    fn site(name: &str, files: &[(&str, &str)]) -> (PathBuf, Config) {
+        let dir = env::temp_dir().join(format!("sitegen-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+
+        let config = format!(
+            r#"
+            [build]
+            out_dir = "{dir}/dist"
+            page_pattern = "{dir}/pages/**/*.handlebars"
+            partials_pattern = "{dir}/partials/**/*.handlebars"
+            post_pattern = "{dir}/posts/**/*.md"
+            style_pattern = "{dir}/styles/**/*.scss"
+
+            [watch]
+            paths = []
+            "#,
+            dir = dir.display()
+        );
+        (dir, toml::from_str(config.as_str()).unwrap())
+    }
+
+    fn post(slug: &str) -> String {
+        format!("+++\nslug = \"{}\"\ntemplate = \"post\"\n+++\nBody\n", slug)
+    }
+
+    async fn check_outputs((dir, config): (PathBuf, Config)) -> Result<()> {
+        let args = Args::parse_from(["sitegen", "build"]);
+        let result = Builder::new(&args, &config).await.check_outputs().await;
+        std::fs::remove_dir_all(dir).unwrap();
+        result
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejects_posts_with_the_same_slug() {
+        let site = site(
+            "post-clash",
+            &[("posts/a.md", &post("a")), ("posts/b.md", &post("a"))],
+        );
+
+        match check_outputs(site).await {
+            Err(Error::DuplicateOutput {
+                output,
+                first,
+                second,
+            }) => {
+                assert_eq!(output, Path::new("a.html"));
+                assert!(first.ends_with("posts/a.md"));
+                assert!(second.ends_with("posts/b.md"));
+            }
+            result => panic!("expected a duplicate output, got {:?}", result.err()),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejects_pages_written_over_posts() {
+        let site = site(
+            "page-clash",
+            &[("posts/a.md", &post("a")), ("pages/a.handlebars", "A")],
+        );
 
-    let file_copy = FileCopier::new(config.build.copy.as_ref(), config.build.out_dir.as_str());
-    file_copy.copy().await?;
+        match check_outputs(site).await {
+            Err(Error::DuplicateOutput {
+                output,
+                first,
+                second,
+            }) => {
+                assert_eq!(output, Path::new("a.html"));
+                assert!(first.ends_with("posts/a.md"));
+                assert!(second.ends_with("pages/a.handlebars"));
+            }
+            result => panic!("expected a duplicate output, got {:?}", result.err()),
+        }
+    }
 
-    log::info!("Build complete");
+    #[tokio::test(flavor = "multi_thread")]
+    async fn accepts_distinct_outputs() {
+        let site = site(
+            "distinct",
+            &[
+                ("posts/a.md", &post("a")),
+                ("posts/b.md", &post("b")),
+                ("pages/index.handlebars", "Index"),
+            ],
+        );
 
-    Ok(())
+        assert!(check_outputs(site).await.is_ok());
+    }
 }
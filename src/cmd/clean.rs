@@ -1,9 +1,21 @@
 use crate::args::Args;
+use crate::build_cache::BuildCache;
 use crate::config::Config;
+use crate::error::Result;
+use crate::staging::previous_dir;
 use tokio::fs;
 
-pub async fn clean(_args: &Args, config: &Config) {
+pub async fn clean(args: &Args, config: &Config) -> Result<()> {
     fs::remove_dir_all(config.build.out_dir.as_str())
         .await
-        .unwrap_or_default()
+        .unwrap_or_default();
+    fs::remove_dir_all(previous_dir(&config.build))
+        .await
+        .unwrap_or_default();
+
+    if args.purge_cache {
+        BuildCache::purge().await?;
+    }
+
+    Ok(())
 }
@@ -1,10 +1,202 @@
+use crate::error::{Error, Result};
+use percent_encoding::percent_decode_str;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Command;
 
-pub async fn http_listen(cmd: &str, args: &[String]) {
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+
+pub async fn http_listen(cmd: &str, args: &[String]) -> Result<()> {
     Command::new(cmd)
         .args(args)
         .kill_on_drop(true)
         .status()
         .await
-        .expect("Unable to start HTTP server");
+        .map(|_| ())
+        .map_err(Error::io(cmd))
+}
+
+/// Serves the files under `root` over HTTP until the process exits
+pub async fn static_listen(port: u16, root: PathBuf) -> Result<()> {
+    let address = format!("localhost:{}", port);
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| Error::Listen(address, e))?;
+
+    loop {
+        let (stream, _) = listener.accept().await.map_err(Error::Network)?;
+        let root = root.clone();
+        tokio::spawn(async move {
+            if let Err(error) = serve_connection(stream, root.as_path()).await {
+                log::debug!("http connection failed: {}", error);
+            }
+        });
+    }
+}
+
+async fn serve_connection(mut stream: TcpStream, root: &Path) -> Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0; 1024];
+
+    let (method, target) = loop {
+        let n = stream.read(&mut chunk).await.map_err(Error::Network)?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buf) {
+            Ok(httparse::Status::Complete(_)) => {
+                let method = request.method.unwrap_or_default().to_string();
+                let target = request.path.unwrap_or("/").to_string();
+                break (method, target);
+            }
+            Ok(httparse::Status::Partial) if buf.len() < MAX_HEADER_SIZE => continue,
+            _ => return respond(&mut stream, 400, "text/plain", b"Bad Request", true).await,
+        }
+    };
+
+    let head_only = method == "HEAD";
+    if method != "GET" && !head_only {
+        return respond(
+            &mut stream,
+            405,
+            "text/plain",
+            b"Method Not Allowed",
+            head_only,
+        )
+        .await;
+    }
+
+    let url_path = target.split(['?', '#']).next().unwrap_or("/");
+    let Some(mut path) = resolve(root, url_path) else {
+        return respond(&mut stream, 400, "text/plain", b"Bad Request", head_only).await;
+    };
+
+    if fs::metadata(&path)
+        .await
+        .map(|m| m.is_dir())
+        .unwrap_or_default()
+    {
+        // Directories are served at a trailing slash so relative links inside
+        // their index.html resolve correctly
+        if !url_path.ends_with('/') {
+            let location = format!("{}/", url_path);
+            return redirect(&mut stream, location.as_str()).await;
+        }
+        path.push("index.html");
+    }
+
+    log::debug!("{} {} -> {:?}", method, target, path);
+
+    match fs::read(&path).await {
+        Ok(body) => respond(&mut stream, 200, mime_type(&path), &body, head_only).await,
+        Err(_) => {
+            let not_found = root.join("404.html");
+            match fs::read(&not_found).await {
+                Ok(body) => respond(&mut stream, 404, "text/html", &body, head_only).await,
+                Err(_) => respond(&mut stream, 404, "text/plain", b"Not Found", head_only).await,
+            }
+        }
+    }
+}
+
+/// Maps a URL path onto the filesystem, refusing paths that would escape `root`
+fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(url_path).decode_utf8().ok()?;
+    let mut path = root.to_path_buf();
+
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(segment) => path.push(segment),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
+
+async fn redirect(stream: &mut TcpStream, location: &str) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        location
+    );
+
+    stream
+        .write_all(head.as_bytes())
+        .await
+        .map_err(Error::Network)
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+    head_only: bool,
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+
+    stream
+        .write_all(head.as_bytes())
+        .await
+        .map_err(Error::Network)?;
+    if !head_only {
+        stream.write_all(body).await.map_err(Error::Network)?;
+    }
+
+    stream.flush().await.map_err(Error::Network)
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "toml" => "application/toml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
 }
@@ -1,12 +1,12 @@
-use super::build;
-use crate::args::Args;
-use crate::config::Config;
+use super::build::{BuildOutput, Builder};
 use crate::error::Result;
-use std::path::Path;
-use tokio::runtime::Runtime;
+use std::path::PathBuf;
+use tokio::runtime::Handle;
 
-pub fn rebuild<P: AsRef<Path>>(rt: &Runtime, path: P, args: &Args, config: &Config) -> Result<()> {
-    log::info!("change: {}", path.as_ref().to_str().unwrap());
+pub fn rebuild(rt: &Handle, paths: &[PathBuf], builder: &mut Builder) -> Result<BuildOutput> {
+    for path in paths {
+        log::info!("change: {}", path.to_string_lossy());
+    }
 
-    rt.block_on(async { build(args, config).await })
+    rt.block_on(async { builder.rebuild(paths).await })
 }
@@ -1,20 +1,27 @@
-use super::build;
-use super::clean;
-use super::http::http_listen;
+use super::build::Builder;
+use super::http::{http_listen, static_listen};
 use super::rebuild::rebuild;
 use super::websocket;
-use crate::cmd::websocket::WebSocketServer;
+use crate::cmd::websocket::{ReloadMessage, WebSocketServer};
+use crate::compilers::url_path;
 use crate::config::Config;
-use crate::{args::Args, error::Result};
+use crate::deps::{normalize, relative};
+use crate::diagnostic;
+use crate::{
+    args::Args,
+    error::{Error, Result},
+};
+use glob::Pattern;
 use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
+use tokio::runtime::Handle;
 
 pub async fn serve(args: Args, config: Config) -> Result<()> {
     log::info!("booting up; build_mode = {:?}", args.mode);
 
-    clean(&args, &config).await;
-    build(&args, &config).await?;
+    let mut builder = Builder::new(&args, &config).await;
+    builder.build().await?;
 
     let websocket_server = WebSocketServer::new();
     let debounce_ws = websocket_server.clone();
@@ -22,44 +29,83 @@ pub async fn serve(args: Args, config: Config) -> Result<()> {
     let timeout = Duration::from_millis(50);
     let tick_rate = None;
 
-    let mut debouncer = new_debouncer(
-        timeout,
-        tick_rate,
-        move |res: DebounceEventResult| match res {
-            Ok(events) => {
-                let first_path = &events.first().unwrap().path;
-                let rt = tokio::runtime::Builder::new_current_thread()
-                    .build()
-                    .unwrap();
-
-                let build_result = rebuild(&rt, first_path, &args, &debounce_config);
-                if let Err(error) = build_result {
-                    log::error!("{}", error);
+    let rt = Handle::current();
+    let mut debouncer = new_debouncer(timeout, tick_rate, move |res: DebounceEventResult| {
+        if let Ok(events) = res {
+            let paths = events.into_iter().map(|e| e.path).collect::<Vec<_>>();
+            let output = match rebuild(&rt, &paths, &mut builder) {
+                Ok(output) => output,
+                Err(error) => {
+                    eprint!("{}", diagnostic::render(&error));
+                    websocket::broadcast(&rt, &debounce_ws, &ReloadMessage::from(&error));
                     return;
                 }
+            };
 
-                websocket::reload_all(rt, &debounce_ws);
-            }
-            Err(_) => {}
-        },
-    )
-    .unwrap();
+            let out_dir = Path::new(debounce_config.build.out_dir.as_str());
+            let message = if paths
+                .iter()
+                .all(|path| is_stylesheet(&debounce_config, path))
+            {
+                ReloadMessage::Css {
+                    files: url_paths(out_dir, &output.stylesheets),
+                }
+            } else {
+                ReloadMessage::Reload {
+                    files: url_paths(out_dir, &output.pages),
+                }
+            };
+
+            websocket::broadcast(&rt, &debounce_ws, &message);
+        }
+    })
+    .map_err(|e| Error::Watch(None, e))?;
 
     for path in &config.watch.paths {
         debouncer
             .watcher()
             .watch(Path::new(path), notify::RecursiveMode::Recursive)
-            .expect("Unable to watch path");
+            .map_err(|e| Error::Watch(Some(PathBuf::from(path)), e))?;
     }
     log::info!("Watching for changes");
 
-    let http_server_cmd = config.http.command.as_str();
-    let http_server_args = config.http.args.as_deref().unwrap_or_default();
+    let http_config = config.http.as_ref();
+    let http_server_cmd = http_config.and_then(|http| http.command.as_deref());
+    let http_server_args = http_config
+        .and_then(|http| http.args.as_deref())
+        .unwrap_or_default();
+    let http_port = http_config.and_then(|http| http.port).unwrap_or(8080);
+    let out_dir = PathBuf::from(config.build.out_dir.as_str());
 
-    let ws = websocket::listen("localhost:8081", &websocket_server);
-    let http = http_listen(http_server_cmd, http_server_args);
+    let ws = websocket::listen(config.websocket_port(), &websocket_server);
+    let http = async {
+        match http_server_cmd {
+            Some(cmd) => http_listen(cmd, http_server_args).await,
+            None => {
+                log::info!("Serving {:?} at http://localhost:{}", out_dir, http_port);
+                static_listen(http_port, out_dir).await
+            }
+        }
+    };
     log::info!("Webserver running");
 
-    let (result, _) = tokio::join!(ws, http);
-    result
+    tokio::try_join!(ws, http).map(|_| ())
+}
+
+/// Sass sources only feed the Sass compiler, so changes to them can be applied
+/// without reloading the page. Plain CSS is copied, so isn't among the
+/// stylesheets a rebuild reports
+fn is_stylesheet(config: &Config, path: &Path) -> bool {
+    let path = relative(&normalize(path));
+    Pattern::new(config.build.style_pattern.as_str())
+        .map(|pattern| pattern.matches_path(&path))
+        .unwrap_or_default()
+}
+
+fn url_paths(out_dir: &Path, paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(out_dir).ok())
+        .map(|path| format!("/{}", url_path(path)))
+        .collect()
 }
@@ -1,23 +1,55 @@
-use crate::error::{Error, Result};
+use crate::error::{Error, Location, Result};
 use futures_util::{stream::SplitSink, SinkExt, StreamExt, TryStreamExt};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::runtime::Runtime;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
 use tokio_tungstenite::WebSocketStream;
 use tungstenite::Message;
 
 type WsWriter = SplitSink<WebSocketStream<TcpStream>, Message>;
 
+/// Messages sent to the live reload client, serialized as JSON objects tagged
+/// by `type`. `files` are URL paths of the outputs that changed
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ReloadMessage {
+    /// Only stylesheets changed; they can be swapped in place
+    Css { files: Vec<String> },
+    /// Anything else changed; the page must be reloaded
+    Reload { files: Vec<String> },
+    /// The build failed. Shown as an overlay until the next successful build
+    Error {
+        #[serde(flatten)]
+        location: Location,
+        message: String,
+    },
+}
+
+impl From<&Error> for ReloadMessage {
+    fn from(error: &Error) -> Self {
+        Self::Error {
+            location: error.location(),
+            message: error.to_string(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct WebSocketServer {
     clients: Arc<Mutex<HashMap<String, WsWriter>>>,
+    last_error: Arc<Mutex<Option<String>>>,
 }
 
 impl WebSocketServer {
     pub fn new() -> Self {
         let clients = Arc::new(Mutex::new(HashMap::new()));
-        Self { clients }
+        let last_error = Arc::new(Mutex::new(None));
+        Self {
+            clients,
+            last_error,
+        }
     }
 
     fn add_client(&self, address: impl Into<String>, stream: WsWriter) -> Result<()> {
@@ -33,33 +65,55 @@ impl WebSocketServer {
     }
 }
 
-pub async fn listen(address: impl ToSocketAddrs, server: &WebSocketServer) -> Result<()> {
-    let listener = TcpListener::bind(address).await.unwrap();
+pub async fn listen(port: u16, server: &WebSocketServer) -> Result<()> {
+    let address = format!("localhost:{}", port);
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| Error::Listen(address, e))?;
 
     loop {
-        let (stream, _) = listener.accept().await.map_err(Error::Io)?;
+        let (stream, _) = listener.accept().await.map_err(Error::Network)?;
         tokio::spawn(accept_connection(server.clone(), stream));
     }
 }
 
-pub fn reload_all(rt: Runtime, server: &WebSocketServer) {
+pub fn broadcast(rt: &Handle, server: &WebSocketServer, message: &ReloadMessage) {
+    let text = serde_json::to_string(message).unwrap();
+
+    // Pages loaded while the build is broken should still show the error
+    let mut last_error = server.last_error.lock().unwrap();
+    *last_error = match message {
+        ReloadMessage::Error { .. } => Some(text.clone()),
+        _ => None,
+    };
+    drop(last_error);
     let mut guard = server.clients.lock().unwrap();
-    for writer in guard.values_mut() {
+    for (addr, writer) in guard.iter_mut() {
         rt.block_on(async {
-            writer
-                .send(Message::Text(String::from("reload")))
-                .await
-                .unwrap();
+            if let Err(error) = writer.send(Message::Text(text.clone())).await {
+                log::debug!("unable to notify {}: {}", addr, error);
+            }
         });
     }
 }
 
 async fn accept_connection(server: WebSocketServer, stream: TcpStream) -> Result<()> {
-    let addr = stream.peer_addr().unwrap().to_string();
+    let addr = stream.peer_addr().map_err(Error::Network)?.to_string();
     log::debug!("{} connected", addr);
 
-    let ws_stream = tokio_tungstenite::accept_async(stream).await.unwrap();
-    let (writer, reader) = ws_stream.split();
+    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(error) => {
+            log::debug!("handshake with {} failed: {}", addr, error);
+            return Ok(());
+        }
+    };
+    let (mut writer, reader) = ws_stream.split();
+
+    let last_error = server.last_error.lock().unwrap().clone();
+    if let Some(text) = last_error {
+        writer.send(Message::Text(text)).await.unwrap_or_default();
+    }
 
     server.add_client(addr.clone(), writer)?;
 
@@ -0,0 +1,109 @@
+use crate::build_cache::digest;
+use crate::compilers::url_path;
+use crate::error::{Error, Result};
+use crate::staging::write_file;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// Where the asset manifest is written, relative to `out_dir`
+pub const ASSET_MANIFEST_FILE: &str = "assets.json";
+
+/// How many hex digits of the content hash go into a file name
+const FINGERPRINT_LENGTH: usize = 8;
+
+/// A stylesheet or copied file, written to `output` in place of `logical`
+#[derive(Clone, Debug)]
+pub struct Asset {
+    pub logical: PathBuf,
+    pub output: PathBuf,
+}
+
+/// Inserts a hash of `contents` before the extension of `path`, so that
+/// `style.css` becomes e.g. `style.3f2a9c01.css`
+pub fn fingerprinted(path: &Path, contents: &[u8]) -> PathBuf {
+    let hash = digest([contents]);
+    let hash = &hash[..FINGERPRINT_LENGTH];
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, hash, extension.to_string_lossy()),
+        None => format!("{}.{}", stem, hash),
+    };
+
+    path.with_file_name(file_name)
+}
+
+/// Maps the URL paths of assets to the URL paths they were written to. Assets
+/// not in the map resolve to themselves, so templates can use `asset` whether
+/// or not the build fingerprints them
+#[derive(Clone, Debug, Default)]
+pub struct AssetMap {
+    assets: BTreeMap<String, String>,
+}
+
+impl AssetMap {
+    pub fn insert(&mut self, out_dir: &Path, asset: &Asset) {
+        let logical = asset.logical.strip_prefix(out_dir);
+        let output = asset.output.strip_prefix(out_dir);
+        if let (Ok(logical), Ok(output)) = (logical, output) {
+            self.assets.insert(url_path(logical), url_path(output));
+        }
+    }
+
+    /// The URL of an asset, given its path with or without a leading `/`
+    pub fn resolve(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        let resolved = self.assets.get(path).map(String::as_str).unwrap_or(path);
+        format!("/{}", resolved)
+    }
+
+    /// A hash of every mapping, which changes whenever an asset is renamed
+    pub fn digest(&self) -> String {
+        digest(
+            self.assets
+                .iter()
+                .flat_map(|(logical, output)| [logical, output]),
+        )
+    }
+
+    /// Writes the map as a JSON object for deployment scripts and servers
+    pub async fn write(&self, out_dir: &Path) -> Result<PathBuf> {
+        let path = out_dir.join(ASSET_MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(&self.assets).unwrap();
+        write_file(path.as_path(), json)
+            .await
+            .map_err(Error::io(&path))?;
+
+        Ok(path)
+    }
+}
+
+/// `{{asset "style.css"}}` writes the URL the asset was published at
+pub struct AssetHelper {
+    pub assets: Arc<RwLock<AssetMap>>,
+}
+
+impl HelperDef for AssetHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        _ctx: &'rc Context,
+        _rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path = h
+            .param(0)
+            .and_then(|v| v.value().as_str())
+            .ok_or_else(|| RenderError::new("`asset` expects a path parameter"))?;
+        let url = self.assets.read().unwrap().resolve(path);
+
+        out.write(&url)?;
+
+        Ok(())
+    }
+}
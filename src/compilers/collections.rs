@@ -0,0 +1,229 @@
+use crate::config::SortOrder;
+use chrono::format::{Item, StrftimeItems};
+use chrono::DateTime;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, JsonValue, RenderContext, RenderError, ScopedJson,
+};
+use std::cmp::Ordering;
+
+/// Looks up a dotted path such as `front_matter.title` in a JSON value
+pub fn lookup<'a>(value: &'a JsonValue, path: &str) -> Option<&'a JsonValue> {
+    path.split('.')
+        .try_fold(value, |value, key| match value {
+            JsonValue::Object(map) => map.get(key),
+            JsonValue::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        })
+        .filter(|value| !value.is_null())
+}
+
+/// Orders two optional JSON values. Missing values always sort last, regardless
+/// of the sort order
+pub fn compare(a: Option<&JsonValue>, b: Option<&JsonValue>, order: SortOrder) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => order.apply(compare_values(a, b)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn compare_values(a: &JsonValue, b: &JsonValue) -> Ordering {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => {
+            let a = a.as_f64().unwrap_or_default();
+            let b = b.as_f64().unwrap_or_default();
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+        (JsonValue::String(a), JsonValue::String(b)) => {
+            match (
+                DateTime::parse_from_rfc3339(a),
+                DateTime::parse_from_rfc3339(b),
+            ) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            }
+        }
+        (JsonValue::Bool(a), JsonValue::Bool(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    }
+}
+
+fn array_param<'a>(h: &'a Helper, name: &str) -> Result<&'a Vec<JsonValue>, RenderError> {
+    h.param(0)
+        .and_then(|v| v.value().as_array())
+        .ok_or_else(|| RenderError::new(format!("`{}` expects an array parameter", name)))
+}
+
+fn str_param<'a>(h: &'a Helper, index: usize, name: &str) -> Result<&'a str, RenderError> {
+    h.param(index)
+        .and_then(|v| v.value().as_str())
+        .ok_or_else(|| RenderError::new(format!("`{}` expects a key parameter", name)))
+}
+
+/// `(where posts "front_matter.draft" false)` keeps items whose key equals the
+/// given value
+pub struct Where;
+
+impl HelperDef for Where {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        _ctx: &'rc Context,
+        _rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, "where")?;
+        let key = str_param(h, 1, "where")?;
+        let expected = h
+            .param(2)
+            .map(|v| v.value())
+            .ok_or_else(|| RenderError::new("`where` expects a value parameter"))?;
+
+        let filtered = items
+            .iter()
+            .filter(|item| lookup(item, key).unwrap_or(&JsonValue::Null) == expected)
+            .cloned()
+            .collect();
+
+        Ok(ScopedJson::Derived(JsonValue::Array(filtered)))
+    }
+}
+
+/// `(sort_by posts "front_matter.title" order="asc")` sorts items by a key,
+/// descending unless an order is given
+pub struct SortBy;
+
+impl HelperDef for SortBy {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        _ctx: &'rc Context,
+        _rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, "sort_by")?;
+        let key = str_param(h, 1, "sort_by")?;
+        let order = match h.hash_get("order").and_then(|v| v.value().as_str()) {
+            Some(order) => order.parse().map_err(RenderError::new)?,
+            None => SortOrder::default(),
+        };
+
+        let mut sorted = items.clone();
+        sorted.sort_by(|a, b| compare(lookup(a, key), lookup(b, key), order));
+
+        Ok(ScopedJson::Derived(JsonValue::Array(sorted)))
+    }
+}
+
+/// `(limit posts 5)` keeps the first items of an array
+pub struct Limit;
+
+impl HelperDef for Limit {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        _ctx: &'rc Context,
+        _rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, "limit")?;
+        let count = h
+            .param(1)
+            .and_then(|v| v.value().as_u64())
+            .ok_or_else(|| RenderError::new("`limit` expects a count parameter"))?;
+
+        let limited = items.iter().take(count as usize).cloned().collect();
+
+        Ok(ScopedJson::Derived(JsonValue::Array(limited)))
+    }
+}
+
+/// `(group_by posts "published" format="%Y")` groups items by a key into
+/// `{ key, items }` objects, in the order each key is first seen. Date keys can
+/// be reduced with a strftime-style `format` before grouping
+pub struct GroupBy;
+
+impl HelperDef for GroupBy {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _r: &'reg Handlebars<'reg>,
+        _ctx: &'rc Context,
+        _rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let items = array_param(h, "group_by")?;
+        let key = str_param(h, 1, "group_by")?;
+        let format = h.hash_get("format").and_then(|v| v.value().as_str());
+        if let Some(format) = format {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                return Err(RenderError::new(format!(
+                    "`group_by` format `{}` is not a valid date format",
+                    format
+                )));
+            }
+        }
+
+        let mut groups: Vec<(JsonValue, Vec<JsonValue>)> = Vec::new();
+        for item in items {
+            let group_key = match (lookup(item, key), format) {
+                (Some(JsonValue::String(date)), Some(format)) => {
+                    match DateTime::parse_from_rfc3339(date) {
+                        Ok(date) => JsonValue::String(date.format(format).to_string()),
+                        Err(_) => JsonValue::String(date.clone()),
+                    }
+                }
+                (Some(value), _) => value.clone(),
+                (None, _) => JsonValue::Null,
+            };
+
+            match groups.iter_mut().find(|(k, _)| *k == group_key) {
+                Some((_, group)) => group.push(item.clone()),
+                None => groups.push((group_key, vec![item.clone()])),
+            }
+        }
+
+        let groups = groups
+            .into_iter()
+            .map(|(key, items)| serde_json::json!({ "key": key, "items": items }))
+            .collect();
+
+        Ok(ScopedJson::Derived(JsonValue::Array(groups)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str) -> Result<String, RenderError> {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_helper("group_by", Box::new(GroupBy));
+        let posts = json!({
+            "posts": [
+                { "published": "2023-01-02T00:00:00+00:00" },
+                { "published": "2023-05-06T00:00:00+00:00" },
+                { "published": "2022-03-04T00:00:00+00:00" },
+            ]
+        });
+
+        handlebars.render_template(template, &posts)
+    }
+
+    #[test]
+    fn groups_by_formatted_dates() {
+        let rendered = render(
+            r#"{{#each (group_by posts "published" format="%Y")}}{{key}}:{{#each items}}.{{/each}} {{/each}}"#,
+        );
+
+        assert_eq!(rendered.unwrap(), "2023:.. 2022:. ");
+    }
+
+    #[test]
+    fn rejects_invalid_formats() {
+        let rendered = render(r#"{{#each (group_by posts "published" format="%Q")}}{{/each}}"#);
+
+        assert!(rendered.is_err());
+    }
+}
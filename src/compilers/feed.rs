@@ -0,0 +1,267 @@
+use crate::compilers::escape_xml;
+use crate::config::{FeedConfig, FeedContent, FeedsConfig, SiteConfig};
+use crate::error::{Error, Result};
+use crate::post_cache::{Post, PostCache};
+use crate::staging::write_file;
+use chrono::{DateTime, FixedOffset, Local};
+use serde_json::json;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+const DEFAULT_LIMIT: usize = 20;
+
+const RSS_PATH: &str = "rss.xml";
+const ATOM_PATH: &str = "atom.xml";
+const JSON_PATH: &str = "feed.json";
+
+pub struct FeedGenerator<'a> {
+    feeds: &'a FeedsConfig,
+    site: &'a SiteConfig,
+}
+
+impl<'a> FeedGenerator<'a> {
+    pub fn new(site: &'a SiteConfig, feeds: &'a FeedsConfig) -> Self {
+        Self { feeds, site }
+    }
+
+    pub async fn generate<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        post_cache: &PostCache,
+    ) -> Result<Vec<PathBuf>> {
+        let output_path = output_path.as_ref();
+        let mut written = Vec::new();
+
+        // Feeds are always newest first, whatever order pages list posts in.
+        // Posts without a publish date can't be placed in a feed
+        let mut posts = post_cache
+            .posts()
+            .iter()
+            .filter(|post| post.published.is_some())
+            .collect::<Vec<_>>();
+        posts.sort_by_key(|post| std::cmp::Reverse(post.published));
+
+        if let Some(rss) = &self.feeds.rss {
+            let path = rss.path.as_deref().unwrap_or(RSS_PATH);
+            let feed = self.rss(path, self.entries(rss, &posts));
+            written.push(write_feed(output_path, path, feed).await?);
+        }
+
+        if let Some(atom) = &self.feeds.atom {
+            let path = atom.path.as_deref().unwrap_or(ATOM_PATH);
+            let feed = self.atom(path, self.entries(atom, &posts));
+            written.push(write_feed(output_path, path, feed).await?);
+        }
+
+        if let Some(json) = &self.feeds.json {
+            let path = json.path.as_deref().unwrap_or(JSON_PATH);
+            let feed = self.json_feed(path, self.entries(json, &posts));
+            written.push(write_feed(output_path, path, feed).await?);
+        }
+
+        Ok(written)
+    }
+
+    /// Where `generate` writes each configured feed
+    pub fn outputs(&self, output_path: &Path) -> Vec<PathBuf> {
+        [
+            (&self.feeds.rss, RSS_PATH),
+            (&self.feeds.atom, ATOM_PATH),
+            (&self.feeds.json, JSON_PATH),
+        ]
+        .into_iter()
+        .filter_map(|(feed, default)| {
+            let path = feed.as_ref()?.path.as_deref().unwrap_or(default);
+            Some(output_path.join(path))
+        })
+        .collect()
+    }
+
+    fn entries<'p>(&self, feed: &FeedConfig, posts: &[&'p Post]) -> Vec<Entry<'p>> {
+        let limit = feed.limit.or(self.feeds.limit).unwrap_or(DEFAULT_LIMIT);
+        let content = feed.content.or(self.feeds.content).unwrap_or_default();
+
+        posts
+            .iter()
+            .take(limit)
+            .map(|post| Entry {
+                post,
+                url: self.site.url(post.permalink.as_str()),
+                title: post
+                    .front_matter
+                    .get("title")
+                    .and_then(|title| title.as_str())
+                    .unwrap_or(post.slug.as_str()),
+                published: post.published.unwrap(),
+                content,
+            })
+            .collect()
+    }
+
+    fn updated(&self, entries: &[Entry]) -> DateTime<FixedOffset> {
+        entries
+            .first()
+            .map(|entry| entry.published)
+            .unwrap_or_else(|| Local::now().into())
+    }
+
+    fn rss(&self, path: &str, entries: Vec<Entry>) -> String {
+        let mut xml = String::new();
+        let description = self.site.description.as_deref().unwrap_or(&self.site.title);
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str("<channel>\n");
+        element(&mut xml, "title", &self.site.title);
+        element(&mut xml, "link", &self.site.url(""));
+        element(&mut xml, "description", description);
+        let _ = writeln!(
+            xml,
+            "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>",
+            escape_xml(&self.site.url(path))
+        );
+        element(
+            &mut xml,
+            "lastBuildDate",
+            &self.updated(&entries).to_rfc2822(),
+        );
+
+        for entry in &entries {
+            xml.push_str("<item>\n");
+            element(&mut xml, "title", entry.title);
+            element(&mut xml, "link", &entry.url);
+            let _ = writeln!(
+                xml,
+                "<guid isPermaLink=\"true\">{}</guid>",
+                escape_xml(&entry.url)
+            );
+            element(&mut xml, "pubDate", &entry.published.to_rfc2822());
+            element(&mut xml, "description", entry.body());
+            xml.push_str("</item>\n");
+        }
+
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn atom(&self, path: &str, entries: Vec<Entry>) -> String {
+        let mut xml = String::new();
+        let author = self.site.author.as_deref().unwrap_or(&self.site.title);
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        element(&mut xml, "title", &self.site.title);
+        element(&mut xml, "id", &self.site.url(""));
+        let _ = writeln!(xml, "<link href=\"{}\"/>", escape_xml(&self.site.url("")));
+        let _ = writeln!(
+            xml,
+            "<link rel=\"self\" href=\"{}\"/>",
+            escape_xml(&self.site.url(path))
+        );
+        element(&mut xml, "updated", &self.updated(&entries).to_rfc3339());
+        let _ = writeln!(xml, "<author><name>{}</name></author>", escape_xml(author));
+
+        for entry in &entries {
+            let (tag, kind) = match entry.content {
+                FeedContent::Full => ("content", "html"),
+                FeedContent::Summary => ("summary", "text"),
+            };
+
+            xml.push_str("<entry>\n");
+            element(&mut xml, "title", entry.title);
+            element(&mut xml, "id", &entry.url);
+            let _ = writeln!(
+                xml,
+                "<link rel=\"alternate\" href=\"{}\"/>",
+                escape_xml(&entry.url)
+            );
+            element(&mut xml, "published", &entry.published.to_rfc3339());
+            element(&mut xml, "updated", &entry.published.to_rfc3339());
+            let _ = writeln!(
+                xml,
+                "<{} type=\"{}\">{}</{}>",
+                tag,
+                kind,
+                escape_xml(entry.body()),
+                tag
+            );
+            xml.push_str("</entry>\n");
+        }
+
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn json_feed(&self, path: &str, entries: Vec<Entry>) -> String {
+        let items = entries
+            .iter()
+            .map(|entry| {
+                let mut item = json!({
+                    "id": entry.url,
+                    "url": entry.url,
+                    "title": entry.title,
+                    "summary": entry.post.excerpt,
+                    "date_published": entry.published.to_rfc3339(),
+                });
+
+                match entry.content {
+                    FeedContent::Full => item["content_html"] = json!(entry.post.content),
+                    FeedContent::Summary => item["content_text"] = json!(entry.post.excerpt),
+                }
+
+                item
+            })
+            .collect::<Vec<_>>();
+
+        let mut feed = json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.site.title,
+            "home_page_url": self.site.url(""),
+            "feed_url": self.site.url(path),
+            "items": items,
+        });
+
+        if let Some(description) = &self.site.description {
+            feed["description"] = json!(description);
+        }
+
+        if let Some(author) = &self.site.author {
+            feed["authors"] = json!([{ "name": author }]);
+        }
+
+        serde_json::to_string_pretty(&feed).unwrap()
+    }
+}
+
+struct Entry<'a> {
+    post: &'a Post,
+    url: String,
+    title: &'a str,
+    published: DateTime<FixedOffset>,
+    content: FeedContent,
+}
+
+impl<'a> Entry<'a> {
+    fn body(&self) -> &str {
+        match self.content {
+            FeedContent::Full => self.post.content.as_str(),
+            FeedContent::Summary => self.post.excerpt.as_str(),
+        }
+    }
+}
+
+async fn write_feed(output_path: &Path, path: &str, feed: String) -> Result<PathBuf> {
+    let path = output_path.join(path);
+    log::debug!("write feed {:?}", path);
+
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).await.map_err(Error::io(dir))?;
+    write_file(&path, feed).await.map_err(Error::io(&path))?;
+
+    Ok(path)
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    let _ = writeln!(xml, "<{}>{}</{}>", name, escape_xml(text), name);
+}
@@ -1,35 +1,200 @@
-use glob::glob;
-use std::path::Path;
-use tokio::fs::{copy, create_dir_all};
+use glob::Pattern;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, metadata, read, read_dir};
 
+use super::assets::{fingerprinted, Asset};
+use super::output::{glob_base, glob_paths};
+use crate::build_cache::BuildCache;
+use crate::config::CopyConfig;
 use crate::error::{Error, Result};
+use crate::parallel::Jobs;
+use crate::staging;
 
 pub struct FileCopier<'a> {
-    paths: Option<&'a Vec<String>>,
+    paths: Option<&'a Vec<CopyConfig>>,
+    /// Patterns of files and directories to leave out
+    exclude: Option<&'a Vec<String>>,
     out_dir: &'a str,
+    /// Patterns of destinations beneath `out_dir` to put a hash of the
+    /// contents in the names of
+    fingerprint: Option<&'a Vec<String>>,
+    cache: &'a BuildCache,
 }
 
 impl<'a> FileCopier<'a> {
-    pub fn new(paths: Option<&'a Vec<String>>, out_dir: &'a str) -> Self {
-        Self { out_dir, paths }
-    }
-
-    pub async fn copy(&self) -> Result<()> {
-        if let Some(paths) = self.paths {
-            for pattern in paths {
-                let files = glob(pattern).map_err(Error::Pattern)?;
-                for from in files {
-                    let from = from.map_err(Error::Glob)?;
-                    let to = Path::new(self.out_dir).join(from.as_path());
-
-                    create_dir_all(to.parent().unwrap())
-                        .await
-                        .map_err(Error::Io)?;
-                    copy(from.as_path(), to).await.map_err(Error::Io)?;
+    pub fn new(
+        paths: Option<&'a Vec<CopyConfig>>,
+        exclude: Option<&'a Vec<String>>,
+        out_dir: &'a str,
+        fingerprint: Option<&'a Vec<String>>,
+        cache: &'a BuildCache,
+    ) -> Self {
+        Self {
+            out_dir,
+            paths,
+            exclude,
+            fingerprint,
+            cache,
+        }
+    }
+
+    /// Every matching file, and every file within matching directories, with
+    /// where it is copied to before fingerprinting
+    pub async fn files(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let exclude = patterns(self.exclude)?;
+        let excluded = |path: &Path| exclude.iter().any(|pattern| pattern.matches_path(path));
+
+        let mut files = BTreeSet::new();
+        for entry in self.paths.into_iter().flatten() {
+            let pattern = entry.source();
+            for path in glob_paths(pattern)? {
+                for from in walk(path, &excluded).await? {
+                    let to = destination(Path::new(self.out_dir), entry, from.as_path());
+                    files.insert((from, to));
                 }
             }
         }
 
-        Ok(())
+        Ok(files.into_iter().collect())
+    }
+
+    /// Copies every file, returning where each was copied to
+    pub async fn copy(&self, jobs: &Jobs) -> Result<Vec<Asset>> {
+        let fingerprint = patterns(self.fingerprint)?;
+        let fingerprint = &fingerprint;
+
+        let out_dir = Path::new(self.out_dir);
+
+        jobs.try_map(self.files().await?, |(from, to)| async move {
+            let fingerprint = is_fingerprinted(fingerprint, out_dir, to.as_path());
+            self.copy_file(from.as_path(), to, fingerprint).await
+        })
+        .await
+    }
+
+    async fn copy_file(&self, from: &Path, logical: PathBuf, fingerprint: bool) -> Result<Asset> {
+        let metadata = metadata(from).await.map_err(Error::io(from))?;
+        if let Some(output) = self.cache.copied_file(from, &logical, &metadata) {
+            log::debug!("{:?} unchanged, skipping", from);
+            return Ok(Asset { logical, output });
+        }
+
+        let to = if fingerprint {
+            let contents = read(from).await.map_err(Error::io(from))?;
+            fingerprinted(&logical, &contents)
+        } else {
+            logical.clone()
+        };
+
+        let dir = to.parent().unwrap();
+        create_dir_all(dir).await.map_err(Error::io(dir))?;
+        staging::copy_file(from, to.as_path())
+            .await
+            .map_err(Error::io(from))?;
+        self.cache.insert_file(from, &logical, &to, &metadata);
+
+        Ok(Asset {
+            logical,
+            output: to,
+        })
+    }
+}
+
+/// Where `from`, matched by `entry`, is copied to beneath `out_dir`
+fn destination(out_dir: &Path, entry: &CopyConfig, from: &Path) -> PathBuf {
+    let (pattern, to) = match entry {
+        CopyConfig::Pattern(_) => return out_dir.join(from),
+        CopyConfig::Mapped { from, to } => (from, to),
+    };
+
+    // A literal `from` is the directory or file that `to` replaces
+    let base = if pattern.contains(['*', '?', '[']) {
+        glob_base(pattern)
+    } else {
+        PathBuf::from(pattern)
+    };
+
+    match from.strip_prefix(base) {
+        Ok(relative) if !relative.as_os_str().is_empty() => out_dir.join(to).join(relative),
+        _ => out_dir.join(to),
+    }
+}
+
+/// Whether a file copied to `to` gets a fingerprinted name. Patterns match the
+/// destination relative to `out_dir`, not the source
+fn is_fingerprinted(patterns: &[Pattern], out_dir: &Path, to: &Path) -> bool {
+    let relative = to.strip_prefix(out_dir).unwrap_or(to);
+    patterns
+        .iter()
+        .any(|pattern| pattern.matches_path(relative))
+}
+
+fn patterns(patterns: Option<&Vec<String>>) -> Result<Vec<Pattern>> {
+    patterns
+        .into_iter()
+        .flatten()
+        .map(|pattern| Pattern::new(pattern).map_err(Error::pattern(pattern)))
+        .collect()
+}
+
+/// The files at or beneath `path`, leaving out excluded files and directories
+async fn walk(path: PathBuf, excluded: &impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut paths = vec![path];
+
+    while let Some(path) = paths.pop() {
+        if excluded(path.as_path()) {
+            continue;
+        }
+
+        let metadata = metadata(path.as_path()).await.map_err(Error::io(&path))?;
+        if !metadata.is_dir() {
+            files.push(path);
+            continue;
+        }
+
+        let mut entries = read_dir(path.as_path()).await.map_err(Error::io(&path))?;
+        while let Some(entry) = entries.next_entry().await.map_err(Error::io(&path))? {
+            paths.push(entry.path());
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_by_destination() {
+        let out_dir = Path::new("dist");
+        let entry = CopyConfig::Mapped {
+            from: String::from("node_modules/x/dist"),
+            to: String::from("vendor/x"),
+        };
+        let patterns = patterns(Some(&vec![String::from("vendor/**/*.js")])).unwrap();
+
+        let to = destination(out_dir, &entry, Path::new("node_modules/x/dist/lib.js"));
+        assert_eq!(to, Path::new("dist/vendor/x/lib.js"));
+        assert!(is_fingerprinted(&patterns, out_dir, &to));
+
+        let to = destination(out_dir, &entry, Path::new("node_modules/x/dist/lib.css"));
+        assert!(!is_fingerprinted(&patterns, out_dir, &to));
+    }
+
+    #[test]
+    fn ignores_sources_when_fingerprinting() {
+        let out_dir = Path::new("dist");
+        let entry = CopyConfig::Mapped {
+            from: String::from("vendor/*.js"),
+            to: String::from("js"),
+        };
+        let patterns = patterns(Some(&vec![String::from("vendor/**/*.js")])).unwrap();
+
+        let to = destination(out_dir, &entry, Path::new("vendor/app.js"));
+        assert_eq!(to, Path::new("dist/js/app.js"));
+        assert!(!is_fingerprinted(&patterns, out_dir, &to));
     }
 }
@@ -1,16 +1,34 @@
 use crate::{
     args::BuildMode,
+    config::SiteContext,
     error::{Error, Result},
-    post_cache::PostCache,
+    post_cache::{Post, PostCache},
+    staging::write_file,
 };
-use glob::glob;
 use handlebars::{
-    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, Renderable,
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonValue, Output, RenderContext,
+    RenderError, Renderable, TemplateError,
 };
 use serde::Serialize;
-use std::{fs::read_to_string, path::Path};
-use tokio::fs::{self, write};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+use tokio::fs;
 
+use super::assets::{AssetHelper, AssetMap};
+use super::collections::{GroupBy, Limit, SortBy, Where};
+use super::live_reload;
+use super::minify::{minify_html, MinifyOptions};
+use super::output::url_path;
+use super::paginator::{page_count, paginate, PageUrls, Paginator};
+use super::sitemap::{PageMeta, WrittenPage};
 use super::FrontMatter;
 
 struct DevOnly;
@@ -35,7 +53,9 @@ impl HelperDef for DevOnly {
         }
 
         let param = h.param(0).and_then(|v| v.value().as_str()).unwrap_or("");
-        let tpl = r.get_template(param).unwrap();
+        let tpl = r
+            .get_template(param)
+            .ok_or_else(|| RenderError::new(format!("`ifdev` template `{}` not found", param)))?;
 
         tpl.render(r, ctx, rc, out)?;
 
@@ -54,8 +74,13 @@ impl HelperDef for InlineSvg {
         _rc: &mut RenderContext<'reg, 'rc>,
         out: &mut dyn Output,
     ) -> HelperResult {
-        let path = h.param(0).and_then(|v| v.value().as_str()).unwrap();
-        let svg = read_to_string(path)?;
+        let path = h
+            .param(0)
+            .and_then(|v| v.value().as_str())
+            .ok_or_else(|| RenderError::new("`svg` expects a path parameter"))?;
+        let svg = read_to_string(path).map_err(|e| {
+            RenderError::from_error(&format!("`svg` is unable to read `{}`", path), e)
+        })?;
 
         out.write(&svg)?;
 
@@ -64,88 +89,395 @@ impl HelperDef for InlineSvg {
 }
 
 pub struct HandlebarsCompiler<'a> {
+    assets: Arc<RwLock<AssetMap>>,
     build_mode: BuildMode,
     registry: Handlebars<'a>,
+    live_reload: Option<u16>,
+    minify: Option<MinifyOptions>,
+    /// How many bytes minifying has saved since the last call to
+    /// `take_bytes_saved`
+    bytes_saved: AtomicUsize,
+    partials: HashMap<String, PathBuf>,
+    site: JsonValue,
+    written: Mutex<Vec<WrittenPage>>,
 }
 
 impl<'a> HandlebarsCompiler<'a> {
-    pub fn new(build_mode: BuildMode) -> Self {
+    pub fn new(build_mode: BuildMode, site: &SiteContext) -> Self {
+        let assets = Arc::new(RwLock::new(AssetMap::default()));
         let mut registry = Handlebars::new();
         registry.set_strict_mode(true);
         registry.set_dev_mode(build_mode == BuildMode::Development);
 
         registry.register_helper("ifdev", Box::new(DevOnly));
         registry.register_helper("svg", Box::new(InlineSvg));
+        registry.register_helper(
+            "asset",
+            Box::new(AssetHelper {
+                assets: assets.clone(),
+            }),
+        );
+        registry.register_helper("where", Box::new(Where));
+        registry.register_helper("sort_by", Box::new(SortBy));
+        registry.register_helper("limit", Box::new(Limit));
+        registry.register_helper("group_by", Box::new(GroupBy));
+
+        let site = serde_json::to_value(site).unwrap_or_default();
 
         Self {
+            assets,
             build_mode,
+            live_reload: None,
+            minify: None,
+            bytes_saved: AtomicUsize::new(0),
+            partials: HashMap::new(),
             registry,
+            site,
+            written: Mutex::new(Vec::new()),
         }
     }
 
-    pub async fn add_partials(&mut self, pattern: &str) -> Result<()> {
-        let partials = glob(pattern).map_err(Error::Pattern)?;
+    /// Injects a client into every page that reloads it when the websocket
+    /// server on `websocket_port` says so. Has no effect on release builds
+    pub fn enable_live_reload(&mut self, websocket_port: u16) {
+        if !self.build_mode.is_release() {
+            self.live_reload = Some(websocket_port);
+        }
+    }
 
-        for partial in partials {
-            let partial = partial.map_err(Error::Glob)?;
-            let name = partial.as_path().file_stem().unwrap().to_str().unwrap();
-            let content = fs::read_to_string(&partial).await.map_err(Error::Io)?;
+    /// Minifies every page written from now on
+    pub fn enable_minify(&mut self, options: MinifyOptions) {
+        self.minify = Some(options);
+    }
 
-            log::debug!("adding partial {}", name);
-            self.registry.register_partial(name, content).unwrap();
-        }
+    /// Sets the URLs the `asset` helper resolves to
+    pub fn set_assets(&self, assets: AssetMap) {
+        *self.assets.write().unwrap() = assets;
+    }
 
-        Ok(())
+    /// Registers a partial file under its file stem, returning the name and
+    /// source it was registered with
+    pub async fn add_partial(&mut self, partial: &Path) -> Result<(String, String)> {
+        let name = partial
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| Error::InvalidPartialName(partial.to_path_buf()))?
+            .to_string();
+        let content = fs::read_to_string(partial)
+            .await
+            .map_err(Error::io(partial))?;
+
+        log::debug!("adding partial {}", name);
+        self.registry
+            .register_partial(name.as_str(), content.as_str())
+            .map_err(|e| self.render_error(partial, 1)(e.into()))?;
+        self.partials.insert(name.clone(), partial.to_path_buf());
+
+        Ok((name, content))
+    }
+
+    pub fn remove_partial(&mut self, name: &str) {
+        log::debug!("removing partial {}", name);
+        self.registry.unregister_template(name);
+        self.partials.remove(name);
     }
 
-    pub async fn compile_all<P: AsRef<Path>>(
+    /// Renders a page to `path` beneath `output_path`, which also decides the
+    /// URLs of any further pages it is paginated into
+    pub async fn compile_page(
         &self,
-        pattern: &str,
-        output_path: P,
+        page: &Path,
+        contents: &str,
+        output_path: &Path,
+        path: PathBuf,
         post_cache: &PostCache,
     ) -> Result<()> {
-        let pages = glob(pattern).map_err(Error::Pattern)?;
+        log::debug!("render {:?} -> {:?}", page, path);
+
+        let dev_mode = self.build_mode == BuildMode::Development;
+        let posts = post_cache.posts();
+        let (fm, template) = split_front_matter(page, contents)?;
+        // The template follows the front matter, so starts this many lines in
+        let first_line = contents[..contents.len() - template.len()]
+            .matches('\n')
+            .count()
+            + 1;
+        let fm_value = |key: &str| fm.as_ref().and_then(|fm| fm.get(key));
+
+        let dev_only = fm_value("dev_only")
+            .and_then(|dev_only| dev_only.as_bool())
+            .unwrap_or_default();
+        if dev_only && !dev_mode {
+            log::debug!("skipping dev-only page {:?}", page);
+            return Ok(());
+        }
 
-        for page in pages {
-            let page = page.map_err(Error::Glob)?;
-            let file_name = page.file_name().unwrap();
-            let mut path = output_path.as_ref().join(file_name);
-            path.set_extension("html");
+        let meta = PageMeta {
+            lastmod: None,
+            sitemap: fm_value("sitemap")
+                .and_then(|sitemap| sitemap.as_bool())
+                .unwrap_or(true),
+        };
+        let per_page = fm_value("paginate").and_then(|paginate| paginate.as_integer());
 
-            log::debug!("render {:?} -> {:?}", page, path);
+        let per_page = match per_page {
+            Some(per_page) => per_page as usize,
+            None => {
+                let context = PageContext {
+                    dev_mode,
+                    posts,
+                    paginator: None,
+                };
+                let rendered = self
+                    .render_page(template, &context)
+                    .map_err(self.render_error(page, first_line))?;
+                let dir = path.parent().unwrap();
+                fs::create_dir_all(dir).await.map_err(Error::io(dir))?;
+                return self.write_page(&path, rendered, meta).await;
+            }
+        };
 
-            let dev_mode = self.build_mode == BuildMode::Development;
-            let posts = post_cache.posts();
-            let context = PageContext { dev_mode, posts };
-            let contents = fs::read_to_string(page).await.map_err(Error::Io)?;
+        let (first_url, base_url) = page_urls(output_path, &path);
+        let urls = PageUrls {
+            first_url: first_url.as_str(),
+            base_url: base_url.as_str(),
+        };
+
+        for paginator in paginate(posts, per_page, &urls) {
+            let page_path = if paginator.page == 1 {
+                path.clone()
+            } else {
+                urls.path(output_path, paginator.page)
+            };
+
+            log::debug!("render page {} -> {:?}", paginator.page, page_path);
+
+            let context = PageContext {
+                dev_mode,
+                posts,
+                paginator: Some(paginator),
+            };
             let rendered = self
-                .registry
-                .render_template(contents.as_str(), &context)
-                .map_err(Error::HandlebarsError)?;
+                .render_page(template, &context)
+                .map_err(self.render_error(page, first_line))?;
 
-            write(&path, rendered.as_str()).await.map_err(Error::Io)?;
+            let dir = page_path.parent().unwrap();
+            fs::create_dir_all(dir).await.map_err(Error::io(dir))?;
+            self.write_page(&page_path, rendered, meta).await?;
         }
 
         Ok(())
     }
 
+    /// Where `compile_page` writes a page, given the `post_count` posts a
+    /// paginated page lists. Development-only pages aren't written in release
+    /// builds
+    pub fn page_outputs(
+        &self,
+        page: &Path,
+        contents: &str,
+        output_path: &Path,
+        path: PathBuf,
+        post_count: usize,
+    ) -> Result<Vec<PathBuf>> {
+        let (fm, _) = split_front_matter(page, contents)?;
+        let fm_value = |key: &str| fm.as_ref().and_then(|fm| fm.get(key));
+
+        let dev_only = fm_value("dev_only")
+            .and_then(|dev_only| dev_only.as_bool())
+            .unwrap_or_default();
+        if dev_only && self.build_mode != BuildMode::Development {
+            return Ok(Vec::new());
+        }
+
+        let Some(per_page) = fm_value("paginate").and_then(|paginate| paginate.as_integer()) else {
+            return Ok(vec![path]);
+        };
+
+        let (first_url, base_url) = page_urls(output_path, &path);
+        let urls = PageUrls {
+            first_url: first_url.as_str(),
+            base_url: base_url.as_str(),
+        };
+        let pages = page_count(post_count, per_page as usize);
+
+        let mut outputs = vec![path];
+        outputs.extend((2..=pages).map(|page| urls.path(output_path, page)));
+        Ok(outputs)
+    }
+
+    fn render_page(
+        &self,
+        template: &str,
+        context: &PageContext,
+    ) -> std::result::Result<String, RenderError> {
+        let data = self.with_site(context)?;
+        self.registry.render_template(template, &data)
+    }
+
+    /// Adds the `site` object to a template context
+    fn with_site<S: Serialize>(&self, data: S) -> std::result::Result<JsonValue, RenderError> {
+        let mut data = serde_json::to_value(data)?;
+        if let JsonValue::Object(map) = &mut data {
+            map.insert(String::from("site"), self.site.clone());
+        }
+
+        Ok(data)
+    }
+
+    /// Renders a registered template to `path`. Errors outside of a partial
+    /// are attributed to `source`
     pub async fn render_to_write<S: Serialize, P: AsRef<Path>>(
         &self,
         template: &str,
         data: S,
         path: P,
+        meta: PageMeta,
+        source: &Path,
     ) -> Result<()> {
         let rendered = self
-            .registry
-            .render(template, &data)
-            .map_err(Error::HandlebarsError)?;
+            .with_site(data)
+            .and_then(|data| self.registry.render(template, &data))
+            .map_err(self.render_error(source, 1))?;
+
+        self.write_page(path.as_ref(), rendered, meta).await
+    }
+
+    async fn write_page(&self, path: &Path, rendered: String, meta: PageMeta) -> Result<()> {
+        let rendered = match self.live_reload {
+            Some(websocket_port) => live_reload::inject(rendered.as_str(), websocket_port),
+            None => rendered,
+        };
+        let rendered = match &self.minify {
+            Some(options) => {
+                let minified = minify_html(rendered.as_str(), options);
+                let saved = rendered.len().saturating_sub(minified.len());
+                self.bytes_saved.fetch_add(saved, Ordering::Relaxed);
+                minified
+            }
+            None => rendered,
+        };
+
+        write_file(path, rendered.as_str())
+            .await
+            .map_err(Error::io(path))?;
+        self.record_page(path, meta);
 
-        write(path, rendered.as_str()).await.map_err(Error::Io)
+        Ok(())
+    }
+
+    /// Records a page as written without rendering it, for pages that are
+    /// already up to date on disk
+    pub fn record_page(&self, path: &Path, meta: PageMeta) {
+        let page = WrittenPage {
+            path: path.to_path_buf(),
+            meta,
+        };
+        self.written.lock().unwrap().push(page);
+    }
+
+    /// Attributes a render error to the partial it occurred in, or else to
+    /// `source`, whose template starts on `first_line`
+    fn render_error<'b>(
+        &'b self,
+        source: &'b Path,
+        first_line: usize,
+    ) -> impl FnOnce(RenderError) -> Error + 'b {
+        move |error| {
+            let cause = error
+                .source()
+                .and_then(|cause| cause.downcast_ref::<TemplateError>());
+            let name = error
+                .template_name
+                .clone()
+                .or_else(|| cause.and_then(|cause| cause.template_name.clone()));
+            let line = error
+                .line_no
+                .or_else(|| cause.and_then(|cause| cause.line_no));
+            let column = error
+                .column_no
+                .or_else(|| cause.and_then(|cause| cause.column_no));
+
+            match name.and_then(|name| self.partials.get(&name)) {
+                Some(partial) => Error::Handlebars {
+                    path: partial.clone(),
+                    line,
+                    column,
+                    error: Box::new(error),
+                },
+                None => Error::Handlebars {
+                    path: source.to_path_buf(),
+                    line: line.map(|line| line + first_line - 1),
+                    column,
+                    error: Box::new(error),
+                },
+            }
+        }
+    }
+
+    /// How many bytes minifying has saved since the last call
+    pub fn take_bytes_saved(&self) -> usize {
+        self.bytes_saved.swap(0, Ordering::Relaxed)
+    }
+
+    /// Every HTML page written since the last call, in the order they were
+    /// written
+    pub fn take_written_pages(&self) -> Vec<WrittenPage> {
+        self.written.lock().unwrap().drain(..).collect()
+    }
+}
+
+/// The URLs of the first and later pages of a paginated page written to
+/// `path`. An index page continues in its own directory, and any other page in
+/// a directory named after it
+fn page_urls(output_path: &Path, path: &Path) -> (String, String) {
+    let relative = url_path(
+        &path
+            .strip_prefix(output_path)
+            .unwrap_or(path)
+            .with_extension(""),
+    );
+
+    match relative.strip_suffix("index") {
+        Some(dir) if dir.is_empty() || dir.ends_with('/') => {
+            (format!("/{}", dir), format!("/{}", dir))
+        }
+        _ => (format!("/{}.html", relative), format!("/{}/", relative)),
+    }
+}
+
+/// Splits an optional TOML front matter block, fenced by `+++` lines, from the
+/// top of a page template
+fn split_front_matter<'a>(
+    page: &Path,
+    contents: &'a str,
+) -> Result<(Option<FrontMatter>, &'a str)> {
+    let fenced = contents
+        .strip_prefix("+++\n")
+        .or_else(|| contents.strip_prefix("+++\r\n"));
+
+    let Some(rest) = fenced else {
+        return Ok((None, contents));
+    };
+
+    let end = rest
+        .match_indices("+++")
+        .find(|(i, _)| *i == 0 || rest[..*i].ends_with('\n'))
+        .map(|(i, _)| i);
+
+    match end {
+        Some(end) => {
+            let fm = toml::from_str(&rest[..end]).map_err(Error::toml(page, &rest[..end], 2))?;
+            let template = rest[end + 3..].trim_start_matches(['\r', '\n']);
+            Ok((Some(fm), template))
+        }
+        None => Err(Error::MissingFrontMatter(page.to_path_buf())),
     }
 }
 
 #[derive(Serialize)]
 struct PageContext<'a> {
     dev_mode: bool,
-    posts: &'a [FrontMatter],
+    posts: &'a [Post],
+    paginator: Option<Paginator<'a, Post>>,
 }
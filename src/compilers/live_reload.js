@@ -0,0 +1,93 @@
+(function () {
+  var address = "ws://" + location.hostname + ":__SITEGEN_WS_PORT__";
+  var retryDelay = 1000;
+
+  function swapStylesheets(files) {
+    var links = document.querySelectorAll('link[rel="stylesheet"]');
+    Array.prototype.forEach.call(links, function (link) {
+      var url = new URL(link.href, location.href);
+      if (url.origin !== location.origin || files.indexOf(url.pathname) === -1) {
+        return;
+      }
+
+      // Load the new stylesheet alongside the old one so the page never
+      // renders unstyled, then drop the old one
+      var replacement = link.cloneNode();
+      url.searchParams.set("sitegen", Date.now());
+      replacement.href = url.href;
+      replacement.addEventListener("load", function () {
+        link.remove();
+      });
+      link.after(replacement);
+    });
+  }
+
+  var overlayId = "sitegen-error-overlay";
+
+  function showError(error) {
+    hideError();
+
+    var overlay = document.createElement("div");
+    overlay.id = overlayId;
+    overlay.style.cssText =
+      "position:fixed;inset:0;z-index:2147483647;overflow:auto;padding:2rem;" +
+      "background:rgba(24,24,27,0.95);color:#f4f4f5;font:14px/1.5 monospace;";
+
+    var title = document.createElement("div");
+    title.style.cssText = "color:#f87171;font-weight:bold;margin-bottom:1rem;";
+    title.textContent = "Build failed";
+    overlay.appendChild(title);
+
+    if (error.file) {
+      var position = document.createElement("div");
+      position.style.cssText = "color:#a1a1aa;margin-bottom:1rem;";
+      position.textContent = [error.file, error.line, error.column]
+        .filter(function (part) {
+          return part !== null && part !== undefined;
+        })
+        .join(":");
+      overlay.appendChild(position);
+    }
+
+    var message = document.createElement("pre");
+    message.style.cssText = "margin:0;white-space:pre-wrap;";
+    message.textContent = error.message;
+    overlay.appendChild(message);
+
+    document.body.appendChild(overlay);
+  }
+
+  function hideError() {
+    var overlay = document.getElementById(overlayId);
+    if (overlay) {
+      overlay.remove();
+    }
+  }
+
+  function connect() {
+    var socket = new WebSocket(address);
+
+    socket.addEventListener("open", function () {
+      retryDelay = 1000;
+    });
+
+    socket.addEventListener("message", function (event) {
+      var message = JSON.parse(event.data);
+      if (message.type === "css") {
+        hideError();
+        swapStylesheets(message.files);
+      } else if (message.type === "reload") {
+        location.reload();
+      } else if (message.type === "error") {
+        showError(message);
+      }
+    });
+
+    socket.addEventListener("close", function () {
+      setTimeout(connect, retryDelay);
+      retryDelay = Math.min(retryDelay * 2, 10000);
+    });
+  }
+
+  connect();
+})();
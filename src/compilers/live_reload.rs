@@ -0,0 +1,20 @@
+const CLIENT: &str = include_str!("live_reload.js");
+
+/// Injects the live reload client just before the closing `</body>` tag, or at
+/// the end of documents without one
+pub fn inject(html: &str, websocket_port: u16) -> String {
+    let script = format!(
+        "<script>{}</script>",
+        CLIENT.replace("__SITEGEN_WS_PORT__", websocket_port.to_string().as_str())
+    );
+    let position = html
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(html.len());
+
+    let mut injected = String::with_capacity(html.len() + script.len());
+    injected.push_str(&html[..position]);
+    injected.push_str(script.as_str());
+    injected.push_str(&html[position..]);
+    injected
+}
@@ -1,24 +1,43 @@
 use crate::args::BuildMode;
-use crate::compilers::HandlebarsCompiler;
+use crate::build_cache::BuildCache;
+use crate::compilers::{glob_paths, post_terms, HandlebarsCompiler, PageMeta, Term};
+use crate::config::TaxonomyConfig;
 use crate::error::{Error, Result};
-use crate::post_cache::PostCache;
-use chrono::{Local, TimeZone};
-use glob::glob;
-use markdown::mdast::{Node, Root, Toml};
+use crate::parallel::Jobs;
+use crate::post_cache::{Post, PostCache};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use markdown::mdast::{Code, InlineCode, Node, Root, Text, Toml};
 use markdown::{Constructs, Options, ParseOptions};
-use std::path::Path;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use toml::value::Offset;
 
 pub type FrontMatter = toml::Table;
 
-pub struct MarkdownCompiler {
+const EXCERPT_WORDS: usize = 50;
+
+/// Where a post will be written, worked out without rendering it
+pub struct PlannedPost {
+    pub source: PathBuf,
+    pub output: PathBuf,
+    pub taxonomies: BTreeMap<String, Vec<Term>>,
+}
+
+pub struct MarkdownCompiler<'a> {
     build_mode: BuildMode,
-    fm_parse_options: ParseOptions,
-    options: Options,
+    cache: &'a BuildCache,
+    constructs: Constructs,
+    taxonomies: &'a [TaxonomyConfig],
 }
 
-impl MarkdownCompiler {
-    pub fn new(build_mode: BuildMode) -> Self {
+impl<'a> MarkdownCompiler<'a> {
+    pub fn new(
+        build_mode: BuildMode,
+        taxonomies: &'a [TaxonomyConfig],
+        cache: &'a BuildCache,
+    ) -> Self {
         let constructs = Constructs {
             frontmatter: true,
             gfm_footnote_definition: true,
@@ -26,123 +45,270 @@ impl MarkdownCompiler {
             ..Default::default()
         };
 
-        let options = Options {
-            parse: ParseOptions {
-                constructs,
-                ..Default::default()
-            },
-            ..Options::gfm()
-        };
-
-        let fm_parse_options = ParseOptions {
-            constructs: options.parse.constructs.clone(),
-            ..Default::default()
-        };
-
         Self {
             build_mode,
-            fm_parse_options,
-            options,
+            cache,
+            constructs,
+            taxonomies,
         }
     }
 
-    pub async fn compile<'a, P: AsRef<Path>>(
+    pub async fn compile<P: AsRef<Path>>(
         &self,
         pattern: &str,
         output_path: P,
-        handlebars: &'a HandlebarsCompiler<'a>,
+        handlebars: &HandlebarsCompiler<'_>,
+        jobs: &Jobs,
     ) -> Result<PostCache> {
         let mut post_cache = PostCache::new();
-        let posts = glob(pattern).map_err(Error::Pattern)?;
+        let posts = glob_paths(pattern)?;
         let output_path = output_path.as_ref();
 
-        for post in posts {
-            let post = post.map_err(Error::Glob)?;
-            let post = self
-                .render_post(&post.as_path(), handlebars, output_path)
-                .await?;
+        let posts = jobs
+            .try_map(posts, |post| async move {
+                self.render_post(post.as_path(), handlebars, output_path)
+                    .await
+            })
+            .await?;
 
-            post.map(|p| post_cache.add_ref(p));
+        for post in posts.into_iter().flatten() {
+            post_cache.add_ref(post);
         }
 
         Ok(post_cache)
     }
 
-    async fn render_post(
+    pub async fn render_post(
         &self,
-        post: &Path,
+        source: &Path,
         handlebars: &HandlebarsCompiler<'_>,
         output_path: &Path,
-    ) -> Result<Option<FrontMatter>> {
-        let content = fs::read_to_string(post).await.map_err(Error::Io)?;
-        let md = markdown::to_html_with_options(content.as_str(), &self.options)
-            .map_err(Error::MarkdownError)?;
-        let fm = self.parse_front_matter(content.as_str())?;
+    ) -> Result<Option<Post>> {
+        let content = fs::read_to_string(source)
+            .await
+            .map_err(Error::io(source))?;
+        let hash = self.cache.post_hash(content.as_str());
+        if let Some(post) = self.cache.post(source, hash.as_str()) {
+            if self.build_mode.is_release() && !is_published(post.published.as_ref()) {
+                return Ok(None);
+            }
 
-        if self.build_mode.is_release() && !is_published(&fm) {
+            log::debug!("{:?} unchanged, skipping", source);
+            let path = post_path(output_path, post.slug.as_str());
+            handlebars.record_page(&path, page_meta(&post.front_matter, post.published));
+            return Ok(Some(post));
+        }
+
+        let options = Options {
+            parse: self.parse_options(),
+            ..Options::gfm()
+        };
+
+        let markdown_error = |error| Error::Markdown(source.to_path_buf(), error);
+        let content_html =
+            markdown::to_html_with_options(content.as_str(), &options).map_err(markdown_error)?;
+        let ast =
+            markdown::to_mdast(content.as_str(), &self.parse_options()).map_err(markdown_error)?;
+        let fm = front_matter(source, &ast)?;
+        let published = front_matter_date(&fm, "published");
+
+        if self.build_mode.is_release() && !is_published(published.as_ref()) {
             return Ok(None);
         }
 
-        let template = fm.get("template").and_then(|tpl| tpl.as_str()).unwrap();
-        let slug = slug(&fm).unwrap();
-        let mut path = output_path.join(slug);
-        path.set_extension("html");
+        let template = fm
+            .get("template")
+            .and_then(|tpl| tpl.as_str())
+            .ok_or_else(|| Error::MissingField(source.to_path_buf(), "template"))?;
+        let slug = slug(&fm).ok_or_else(|| Error::MissingField(source.to_path_buf(), "slug"))?;
+        let path = post_path(output_path, slug);
+
+        log::debug!("{:?} -> {:?}", source, path);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).await.map_err(Error::io(dir))?;
+
+        let mut text = String::new();
+        plain_text(&ast, &mut text);
+        let words = text.split_whitespace().collect::<Vec<_>>();
+
+        let permalink = format!("/{}.html", slug);
+        let post = Post {
+            slug: slug.to_string(),
+            url: permalink.clone(),
+            permalink,
+            published,
+            source: source.to_path_buf(),
+            content: content_html,
+            excerpt: excerpt(&words),
+            word_count: words.len(),
+            taxonomies: post_terms(self.taxonomies, &fm),
+            front_matter: fm.clone(),
+        };
+
+        let context = PostContext {
+            post: &post,
+            dev_mode: !self.build_mode.is_release(),
+            mode: self.build_mode.to_string(),
+        };
+
+        handlebars
+            .render_to_write(template, &context, &path, page_meta(&fm, published), source)
+            .await?;
+        self.cache.insert_post(hash, &path, &post);
+
+        Ok(Some(post))
+    }
 
-        log::debug!("{:?} -> {:?}", post, path);
-        fs::create_dir_all(path.parent().unwrap())
+    /// Reads where a post will be written and the terms it lists from its
+    /// front matter. `None` for posts a release build leaves out
+    pub async fn plan_post(
+        &self,
+        source: &Path,
+        output_path: &Path,
+    ) -> Result<Option<PlannedPost>> {
+        let content = fs::read_to_string(source)
             .await
-            .map_err(Error::Io)?;
+            .map_err(Error::io(source))?;
+        let ast = markdown::to_mdast(content.as_str(), &self.parse_options())
+            .map_err(|error| Error::Markdown(source.to_path_buf(), error))?;
+        let fm = front_matter(source, &ast)?;
+
+        let published = front_matter_date(&fm, "published");
+        if self.build_mode.is_release() && !is_published(published.as_ref()) {
+            return Ok(None);
+        }
 
-        handlebars.render_to_write(template, &fm, &path).await?;
+        let slug = slug(&fm).ok_or_else(|| Error::MissingField(source.to_path_buf(), "slug"))?;
 
-        Ok(Some(fm))
+        Ok(Some(PlannedPost {
+            source: source.to_path_buf(),
+            output: post_path(output_path, slug),
+            taxonomies: post_terms(self.taxonomies, &fm),
+        }))
     }
 
-    fn parse_front_matter(&self, content: &str) -> Result<FrontMatter> {
-        let ast =
-            markdown::to_mdast(content, &self.fm_parse_options).map_err(Error::MarkdownError)?;
+    fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            constructs: self.constructs.clone(),
+            ..Default::default()
+        }
+    }
+}
 
-        if let Node::Root(Root { children, .. }) = ast {
-            if let Some(Node::Toml(Toml { value, .. })) = children.first() {
-                toml::from_str(value).map_err(Error::Toml)
-            } else {
-                Err(Error::MissingFrontMatter)
-            }
+#[derive(Serialize)]
+struct PostContext<'a> {
+    #[serde(flatten)]
+    post: &'a Post,
+    dev_mode: bool,
+    mode: String,
+}
+
+fn front_matter(source: &Path, ast: &Node) -> Result<FrontMatter> {
+    if let Node::Root(Root { children, .. }) = ast {
+        if let Some(Node::Toml(Toml {
+            value, position, ..
+        })) = children.first()
+        {
+            // The value starts on the line after the opening `+++`
+            let first_line = position.as_ref().map(|p| p.start.line + 1).unwrap_or(2);
+            toml::from_str(value).map_err(Error::toml(source, value, first_line))
         } else {
-            unreachable!();
+            Err(Error::MissingFrontMatter(source.to_path_buf()))
         }
+    } else {
+        unreachable!();
     }
 }
 
+/// Collects the readable text of a document, separating block-level nodes with
+/// whitespace so that words in adjacent paragraphs don't run together
+fn plain_text(node: &Node, out: &mut String) {
+    match node {
+        Node::Toml(_) | Node::Yaml(_) | Node::Html(_) => {}
+        Node::Text(Text { value, .. })
+        | Node::InlineCode(InlineCode { value, .. })
+        | Node::Code(Code { value, .. }) => out.push_str(value),
+        _ => {
+            if let Some(children) = node.children() {
+                for child in children {
+                    plain_text(child, out);
+                }
+            }
+        }
+    }
+
+    if matches!(
+        node,
+        Node::Paragraph(_) | Node::Heading(_) | Node::Code(_) | Node::TableCell(_) | Node::Break(_)
+    ) {
+        out.push(' ');
+    }
+}
+
+fn excerpt(words: &[&str]) -> String {
+    let mut excerpt = words
+        .iter()
+        .take(EXCERPT_WORDS)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if words.len() > EXCERPT_WORDS {
+        excerpt.push('…');
+    }
+
+    excerpt
+}
+
+fn post_path(output_path: &Path, slug: &str) -> PathBuf {
+    let mut path = output_path.join(slug);
+    path.set_extension("html");
+    path
+}
+
 fn slug(fm: &FrontMatter) -> Option<&str> {
     fm.get("slug").and_then(|slug| slug.as_str())
 }
 
-fn is_published(fm: &FrontMatter) -> bool {
-    fm.get("published")
-        .and_then(|published| published.as_datetime())
-        .map(|publish_date| {
-            let y: i32;
-            let m: u32;
-            let d: u32;
-
-            match publish_date.date {
-                Some(date) => {
-                    y = date.year as i32;
-                    m = date.month as u32;
-                    d = date.day as u32;
-                }
-                None => {
-                    y = 3000;
-                    m = 1;
-                    d = 1;
-                }
-            }
+fn page_meta(fm: &FrontMatter, published: Option<DateTime<FixedOffset>>) -> PageMeta {
+    PageMeta {
+        lastmod: front_matter_date(fm, "updated").or(published),
+        sitemap: fm
+            .get("sitemap")
+            .and_then(|sitemap| sitemap.as_bool())
+            .unwrap_or(true),
+    }
+}
+
+fn front_matter_date(fm: &FrontMatter, key: &str) -> Option<DateTime<FixedOffset>> {
+    let datetime = fm.get(key).and_then(|value| value.as_datetime())?;
+    let date = datetime.date?;
+    let date = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?;
+    let time = match datetime.time {
+        Some(time) => NaiveTime::from_hms_nano_opt(
+            time.hour as u32,
+            time.minute as u32,
+            time.second as u32,
+            time.nanosecond,
+        )?,
+        None => NaiveTime::from_hms_opt(0, 0, 0)?,
+    };
+    let date_time = date.and_time(time);
 
-            let now = Local::now();
-            let pub_date = Local.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap();
+    match datetime.offset {
+        Some(Offset::Z) => Some(Utc.from_utc_datetime(&date_time).into()),
+        Some(Offset::Custom { minutes }) => FixedOffset::east_opt(minutes as i32 * 60)?
+            .from_local_datetime(&date_time)
+            .single(),
+        None => Local
+            .from_local_datetime(&date_time)
+            .earliest()
+            .map(Into::into),
+    }
+}
 
-            pub_date < now
-        })
+fn is_published(published: Option<&DateTime<FixedOffset>>) -> bool {
+    published
+        .map(|publish_date| *publish_date < Local::now())
         .unwrap_or_default()
 }
@@ -0,0 +1,340 @@
+/// What to minify besides the HTML itself
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinifyOptions {
+    /// Whether to minify inline `<style>` elements
+    pub css: bool,
+    /// Whether to minify inline JavaScript `<script>` elements
+    pub js: bool,
+}
+
+/// Elements whose contents aren't HTML, or whose whitespace is significant
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Minifies an HTML document by stripping comments and collapsing whitespace,
+/// except within `<pre>`, `<textarea>`, `<script>` and `<style>`.
+///
+/// Runs of whitespace become a single space rather than nothing, since between
+/// inline elements they are rendered.
+pub fn minify_html(html: &str, options: &MinifyOptions) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map(|end| &comment[end + 3..])
+                .unwrap_or_default();
+            continue;
+        }
+
+        if let Some(length) = tag_length(rest) {
+            let tag = &rest[..length];
+            push_tag(&mut out, tag);
+            rest = &rest[length..];
+
+            if let Some(name) = raw_element(tag) {
+                let end = closing_tag(rest, name).unwrap_or(rest.len());
+                let contents = &rest[..end];
+                match name {
+                    "style" if options.css => out.push_str(&minify_css(contents)),
+                    "script" if options.js && is_javascript(tag) => {
+                        out.push_str(&minify_js(contents))
+                    }
+                    _ => out.push_str(contents),
+                }
+                rest = &rest[end..];
+            }
+            continue;
+        }
+
+        // Text runs to the next tag. A `<` that doesn't start one is text
+        let skip = if rest.starts_with('<') { 1 } else { 0 };
+        let end = rest[skip..]
+            .find('<')
+            .map(|i| i + skip)
+            .unwrap_or(rest.len());
+        push_text(&mut out, &rest[..end]);
+        rest = &rest[end..];
+    }
+
+    out.trim().to_string()
+}
+
+/// The length of the tag `html` starts with, if it starts with one
+fn tag_length(html: &str) -> Option<usize> {
+    let mut chars = html.char_indices();
+    if chars.next()?.1 != '<' {
+        return None;
+    }
+    if !matches!(chars.next()?.1, 'a'..='z' | 'A'..='Z' | '/' | '!') {
+        return None;
+    }
+
+    let mut quote = None;
+    for (i, c) in chars {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The name of the raw element `tag` opens, if it opens one
+fn raw_element(tag: &str) -> Option<&'static str> {
+    let name = tag[1..]
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .next()?;
+
+    RAW_ELEMENTS
+        .into_iter()
+        .find(|element| element.eq_ignore_ascii_case(name))
+}
+
+/// Where the closing tag of the raw element `name` starts in `html`
+fn closing_tag(html: &str, name: &str) -> Option<usize> {
+    html.match_indices("</").map(|(i, _)| i).find(|i| {
+        let after = &html[i + 2..];
+        match (after.get(..name.len()), after.get(name.len()..)) {
+            (Some(tag), Some(rest)) => {
+                tag.eq_ignore_ascii_case(name)
+                    && rest
+                        .chars()
+                        .next()
+                        .map(|c| c.is_whitespace() || c == '>' || c == '/')
+                        .unwrap_or(true)
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Appends a tag, collapsing whitespace between its attributes
+fn push_tag(out: &mut String, tag: &str) {
+    let mut quote = None;
+    let mut space = false;
+
+    for c in tag.chars() {
+        if quote.is_none() && c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && c != '>' {
+            out.push(' ');
+        }
+        space = false;
+
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => {}
+        }
+        out.push(c);
+    }
+}
+
+/// Appends text, collapsing each run of whitespace into a single space
+fn push_text(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if !c.is_whitespace() {
+            out.push(c);
+        } else if !out.ends_with(' ') {
+            out.push(' ');
+        }
+    }
+}
+
+/// Whether a `<script>` tag holds JavaScript, rather than e.g. JSON or a
+/// template
+fn is_javascript(tag: &str) -> bool {
+    let tag = tag.to_ascii_lowercase();
+    let Some(i) = tag.find(" type=") else {
+        return true;
+    };
+
+    let kind = tag[i + 6..].trim_start_matches(['"', '\'']);
+    ["text/javascript", "application/javascript", "module"]
+        .iter()
+        .any(|known| kind.starts_with(known))
+}
+
+/// Strips comments and whitespace from CSS, keeping the whitespace that
+/// separates words, like descendant combinators and values
+fn minify_css(css: &str) -> String {
+    // Whitespace next to these never matters. `:` only counts when it follows,
+    // since a space before it separates a descendant pseudo-class selector
+    let is_delimiter = |c: char| matches!(c, '{' | '}' | ';' | ',');
+
+    let mut out = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut space = false;
+
+    while let Some(c) = chars.next() {
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut previous = ' ';
+            for c in chars.by_ref() {
+                if previous == '*' && c == '/' {
+                    break;
+                }
+                previous = c;
+            }
+            space = true;
+            continue;
+        }
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+
+        let follows_delimiter = out.ends_with(is_delimiter) || out.ends_with(':');
+        if space && !out.is_empty() && !follows_delimiter && !is_delimiter(c) {
+            out.push(' ');
+        }
+        space = false;
+
+        if c == '}' && out.ends_with(';') {
+            out.pop();
+        }
+        out.push(c);
+
+        if c == '"' || c == '\'' {
+            while let Some(quoted) = chars.next() {
+                out.push(quoted);
+                if quoted == '\\' {
+                    out.extend(chars.next());
+                } else if quoted == c {
+                    break;
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Trims every line of a script and drops blank ones. Line breaks are kept,
+/// since they can end statements. Scripts with template literals are left as
+/// they are, since their indentation may be part of a string
+fn minify_js(js: &str) -> String {
+    if js.contains('`') {
+        return js.to_string();
+    }
+
+    js.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minify(html: &str) -> String {
+        minify_html(html, &MinifyOptions::default())
+    }
+
+    #[test]
+    fn collapses_whitespace() {
+        assert_eq!(
+            minify("<p>\n  Hello   <b>world</b>\n</p>\n"),
+            "<p> Hello <b>world</b> </p>"
+        );
+    }
+
+    #[test]
+    fn keeps_non_ascii_text() {
+        assert_eq!(minify("<h1>Über uns</h1>"), "<h1>Über uns</h1>");
+        assert_eq!(
+            minify("<p>a</p>\n<!-- x -->é <i>ü</i>"),
+            "<p>a</p> é <i>ü</i>"
+        );
+    }
+
+    #[test]
+    fn keeps_non_ascii_raw_elements() {
+        assert_eq!(
+            minify("<pre>ä</é</pre>  <p>ö</p>"),
+            "<pre>ä</é</pre> <p>ö</p>"
+        );
+    }
+
+    #[test]
+    fn keeps_stray_angle_brackets() {
+        assert_eq!(minify("<p>1 < 2  >  0</p>"), "<p>1 < 2 > 0</p>");
+    }
+
+    #[test]
+    fn strips_comments() {
+        assert_eq!(
+            minify("<div> <!-- a\ncomment --> <p>text</p></div>"),
+            "<div> <p>text</p></div>"
+        );
+        assert_eq!(minify("<p>a</p><!-- unterminated"), "<p>a</p>");
+    }
+
+    #[test]
+    fn keeps_pre_and_textarea() {
+        assert_eq!(
+            minify("<pre>\n  a   b\n</pre>  <TEXTAREA rows=2>\n x  y</TEXTAREA>"),
+            "<pre>\n  a   b\n</pre> <TEXTAREA rows=2>\n x  y</TEXTAREA>"
+        );
+        assert_eq!(
+            minify("<pre><code>  a </code>\n</pre>"),
+            "<pre><code>  a </code>\n</pre>"
+        );
+    }
+
+    #[test]
+    fn keeps_quoted_attributes() {
+        assert_eq!(
+            minify("<a   title=\"a > b\"\n   href='x  y'>link</a>"),
+            "<a title=\"a > b\" href='x  y'>link</a>"
+        );
+    }
+
+    #[test]
+    fn minifies_inline_css_when_enabled() {
+        let html = "<style>\n  /* c */\n  a :hover , b {\n    content: \"a  b\" ;\n  }\n</style>";
+        let options = MinifyOptions {
+            css: true,
+            js: false,
+        };
+
+        assert_eq!(
+            minify_html(html, &options),
+            "<style>a :hover,b{content:\"a  b\"}</style>"
+        );
+        assert_eq!(minify(html), html);
+    }
+
+    #[test]
+    fn minifies_inline_javascript_when_enabled() {
+        let options = MinifyOptions {
+            css: false,
+            js: true,
+        };
+
+        assert_eq!(
+            minify_html("<script>\n  var a = 1;\n\n  f(a);\n</script>", &options),
+            "<script>var a = 1;\nf(a);</script>"
+        );
+        assert_eq!(
+            minify_html("<script>\n  var a = `x\n  y`;\n</script>", &options),
+            "<script>\n  var a = `x\n  y`;\n</script>"
+        );
+        assert_eq!(
+            minify_html(
+                "<script type=\"application/ld+json\">\n  {}\n</script>",
+                &options
+            ),
+            "<script type=\"application/ld+json\">\n  {}\n</script>"
+        );
+    }
+}
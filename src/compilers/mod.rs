@@ -1,9 +1,26 @@
+mod assets;
+pub mod collections;
+mod feed;
 mod file_copier;
 mod handlebars;
+mod live_reload;
 mod markdown;
+mod minify;
+mod output;
+mod paginator;
 mod scss;
+mod sitemap;
+mod source_map;
+mod taxonomy;
 
 pub use self::handlebars::HandlebarsCompiler;
-pub use self::markdown::{FrontMatter, MarkdownCompiler};
+pub use self::markdown::{FrontMatter, MarkdownCompiler, PlannedPost};
+pub use assets::{Asset, AssetMap, ASSET_MANIFEST_FILE};
+pub use feed::FeedGenerator;
 pub use file_copier::FileCopier;
-pub use scss::{CompilerOptions, SassCompiler};
+pub use minify::MinifyOptions;
+pub use output::{check_unique_outputs, escape_xml, glob_base, glob_paths, output_path, url_path};
+pub use paginator::{page_count, paginate, PageUrls, Paginator};
+pub use scss::{is_entry_point, CompiledStylesheet, CompilerOptions, SassCompiler};
+pub use sitemap::{PageMeta, SitemapGenerator};
+pub use taxonomy::{post_terms, TaxonomyCompiler, Term};
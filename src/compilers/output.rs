@@ -0,0 +1,150 @@
+use crate::deps::{normalize, relative};
+use crate::error::{Error, Result};
+use glob::glob;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Maps `input`, matched by `pattern`, onto `out_dir` with a new extension,
+/// keeping its path relative to the directory the pattern starts matching in.
+/// `pages/**/*.handlebars` maps `pages/blog/index.handlebars` to
+/// `blog/index.html`. `input` may be absolute, as the dependency graph gives
+/// it, or relative to the working directory
+pub fn output_path(pattern: &str, input: &Path, out_dir: &Path, extension: &str) -> PathBuf {
+    let base = relative(&normalize(&glob_base(pattern)));
+    let input = relative(&normalize(input));
+    let input = input.as_path();
+    let relative = input
+        .strip_prefix(base.as_path())
+        .ok()
+        .filter(|relative| !relative.as_os_str().is_empty())
+        .or_else(|| input.file_name().map(Path::new))
+        .unwrap_or(input);
+
+    let mut path = out_dir.join(relative);
+    path.set_extension(extension);
+    path
+}
+
+/// Fails if two inputs map to the same output beneath `out_dir`
+pub fn check_unique_outputs<'a, I>(out_dir: &Path, outputs: I) -> Result<()>
+where
+    I: IntoIterator<Item = (&'a Path, &'a Path)>,
+{
+    let mut inputs = HashMap::new();
+    for (input, output) in outputs {
+        if let Some(first) = inputs.insert(output, input) {
+            return Err(Error::DuplicateOutput {
+                output: output.strip_prefix(out_dir).unwrap_or(output).to_path_buf(),
+                first: first.to_path_buf(),
+                second: input.to_path_buf(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// The leading directories of a glob pattern that contain no wildcards
+pub fn glob_base(pattern: &str) -> PathBuf {
+    let pattern = Path::new(pattern);
+    let mut base = PathBuf::new();
+    for component in pattern.components() {
+        let literal = component.as_os_str().to_string_lossy();
+        if literal.contains(['*', '?', '[']) {
+            return base;
+        }
+        base.push(component);
+    }
+
+    // A pattern without wildcards matches a single file
+    pattern.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Every path matching a glob pattern
+pub fn glob_paths(pattern: &str) -> Result<Vec<PathBuf>> {
+    glob(pattern)
+        .map_err(Error::pattern(pattern))?
+        .map(|path| path.map_err(Error::Glob))
+        .collect()
+}
+
+/// Joins the components of a relative path with `/`, as they appear in URLs
+pub fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Escapes text for XML elements and attributes
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn maps_relative_inputs() {
+        let output = output_path(
+            "pages/**/*.handlebars",
+            Path::new("pages/blog/index.handlebars"),
+            Path::new("dist"),
+            "html",
+        );
+
+        assert_eq!(output, Path::new("dist/blog/index.html"));
+    }
+
+    #[test]
+    fn maps_absolute_inputs() {
+        let input = env::current_dir()
+            .unwrap()
+            .join("pages/blog/index.handlebars");
+        let output = output_path("pages/**/*.handlebars", &input, Path::new("dist"), "html");
+
+        assert_eq!(output, Path::new("dist/blog/index.html"));
+    }
+
+    #[test]
+    fn maps_inputs_of_patterns_starting_with_dot() {
+        let output = output_path(
+            "./styles/**/*.scss",
+            Path::new("styles/sub/extra.scss"),
+            Path::new("dist"),
+            "css",
+        );
+
+        assert_eq!(output, Path::new("dist/sub/extra.css"));
+    }
+
+    #[test]
+    fn joins_url_paths_with_slashes() {
+        let path = Path::new("blog").join("page").join("index.html");
+
+        assert_eq!(url_path(&path), "blog/page/index.html");
+        assert_eq!(url_path(Path::new("")), "");
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(
+            escape_xml("T & co's <\"feed\">"),
+            "T &amp; co&apos;s &lt;&quot;feed&quot;&gt;"
+        );
+    }
+}
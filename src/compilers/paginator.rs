@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Serialize)]
+pub struct Paginator<'a, T> {
+    pub items: &'a [T],
+    pub page: usize,
+    pub per_page: usize,
+    pub total_items: usize,
+    pub total_pages: usize,
+    pub first: String,
+    pub last: String,
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+/// Where the pages of a paginated listing are published. The first page keeps
+/// its usual URL; later pages are nested under `base_url`, so a listing at
+/// `/blog/` continues at `/blog/page/2/`
+pub struct PageUrls<'a> {
+    pub first_url: &'a str,
+    pub base_url: &'a str,
+}
+
+impl<'a> PageUrls<'a> {
+    pub fn url(&self, page: usize) -> String {
+        if page == 1 {
+            self.first_url.to_string()
+        } else {
+            format!("{}page/{}/", self.base_url, page)
+        }
+    }
+
+    /// The output path of a page after the first
+    pub fn path(&self, output_path: &Path, page: usize) -> PathBuf {
+        output_path
+            .join(self.base_url.trim_start_matches('/'))
+            .join("page")
+            .join(page.to_string())
+            .join("index.html")
+    }
+}
+
+/// Splits `items` into pages of `per_page` items. An empty listing still gets
+/// a single, empty page
+pub fn paginate<'a, T>(items: &'a [T], per_page: usize, urls: &PageUrls) -> Vec<Paginator<'a, T>> {
+    let per_page = per_page.max(1);
+    let total_pages = page_count(items.len(), per_page);
+
+    (1..=total_pages)
+        .map(|page| {
+            let start = (page - 1) * per_page;
+            let end = (start + per_page).min(items.len());
+
+            Paginator {
+                items: &items[start..end],
+                page,
+                per_page,
+                total_items: items.len(),
+                total_pages,
+                first: urls.url(1),
+                last: urls.url(total_pages),
+                previous: (page > 1).then(|| urls.url(page - 1)),
+                next: (page < total_pages).then(|| urls.url(page + 1)),
+            }
+        })
+        .collect()
+}
+
+/// How many pages `paginate` splits `items` items into
+pub fn page_count(items: usize, per_page: usize) -> usize {
+    items.div_ceil(per_page.max(1)).max(1)
+}
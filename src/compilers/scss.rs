@@ -1,42 +1,235 @@
+use crate::build_cache::BuildCache;
+use crate::compilers::{glob_paths, output_path};
+use crate::config::{BuildConfig, CssStyle};
 use crate::error::{Error, Result};
-use glob::glob;
-use grass::Options;
-use std::path::Path;
-use tokio::fs::write;
+use crate::parallel::Jobs;
+use crate::staging::write_file;
+use glob::Pattern;
+use grass::{Fs, Options, OutputStyle, StdFs};
+use std::cell::RefCell;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, read, read_to_string};
+
+use super::assets::fingerprinted;
+use super::source_map::source_map;
 
 pub struct CompilerOptions<'a> {
     pub input_pattern: &'a str,
+    /// Patterns of the stylesheets to compile, instead of every non-partial
+    /// matched by `input_pattern`
+    pub entry_points: Option<&'a Vec<String>>,
+    /// Directories searched by `@use` and `@import`
+    pub load_paths: Option<&'a Vec<String>>,
     pub output_path: &'a str,
+    pub style: CssStyle,
+    /// Whether to write a `.css.map` next to each stylesheet
+    pub source_maps: bool,
+    /// Whether to put a hash of the contents in output file names
+    pub fingerprint: bool,
+    pub cache: &'a BuildCache,
+}
+
+/// The result of compiling a single stylesheet
+pub struct CompiledStylesheet {
+    pub output: PathBuf,
+    /// Where the stylesheet would be written without fingerprinting
+    pub logical: PathBuf,
+    pub source_map: Option<PathBuf>,
+    /// Whether the output's contents changed. Unchanged outputs are left
+    /// untouched
+    pub changed: bool,
+    /// Every file Sass read while compiling, including the entry point
+    pub dependencies: Vec<PathBuf>,
 }
 
 pub struct SassCompiler<'a> {
-    compiler_options: Options<'a>,
     options: CompilerOptions<'a>,
 }
 
 impl<'a> SassCompiler<'a> {
     pub fn new(options: CompilerOptions<'a>) -> Self {
-        let compiler_options = Default::default();
-        Self {
-            compiler_options,
-            options,
+        Self { options }
+    }
+
+    /// The entry points to compile. Partials, whose names start with `_`, are
+    /// only compiled through the stylesheets that load them
+    pub fn stylesheets(&self) -> Result<Vec<PathBuf>> {
+        let Some(entry_points) = self.options.entry_points else {
+            let stylesheets = glob_paths(self.options.input_pattern)?;
+            return Ok(stylesheets
+                .into_iter()
+                .filter(|stylesheet| !is_partial(stylesheet))
+                .collect());
+        };
+
+        let mut stylesheets = Vec::new();
+        for pattern in entry_points {
+            for stylesheet in glob_paths(pattern)? {
+                if !stylesheets.contains(&stylesheet) {
+                    stylesheets.push(stylesheet);
+                }
+            }
+        }
+
+        Ok(stylesheets)
+    }
+
+    /// Each entry point with the stylesheet and source map it compiles to,
+    /// before fingerprinting
+    pub fn outputs(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut outputs = Vec::new();
+        for stylesheet in self.stylesheets()? {
+            let output = self.output_path(&stylesheet);
+            if let Some(source_map) = self.source_map_path(&output) {
+                outputs.push((stylesheet.clone(), source_map));
+            }
+            outputs.push((stylesheet, output));
         }
+
+        Ok(outputs)
     }
 
-    pub async fn compile(&self) -> Result<()> {
-        let stylesheets = glob(self.options.input_pattern).map_err(Error::Pattern)?;
-        for stylesheet in stylesheets {
-            let stylesheet = stylesheet.map_err(Error::Glob)?;
-            let rendered = grass::from_path(stylesheet.as_path(), &self.compiler_options)
-                .map_err(Error::Sass)?;
+    pub async fn compile(&self, jobs: &Jobs) -> Result<Vec<CompiledStylesheet>> {
+        let stylesheets = self.stylesheets()?;
+
+        jobs.try_map(stylesheets, |stylesheet| async move {
+            self.compile_stylesheet(stylesheet.as_path()).await
+        })
+        .await
+    }
+
+    pub async fn compile_stylesheet(&self, stylesheet: &Path) -> Result<CompiledStylesheet> {
+        if let Some((output, dependencies)) = self.options.cache.stylesheet(stylesheet).await {
+            log::debug!("{:?} unchanged, skipping", stylesheet);
+            return Ok(CompiledStylesheet {
+                source_map: self.source_map_path(&output),
+                logical: self.output_path(stylesheet),
+                output,
+                changed: false,
+                dependencies,
+            });
+        }
+
+        let fs = RecordingFs::default();
+        let load_paths = self.options.load_paths.map(Vec::as_slice);
+        let style = match self.options.style {
+            CssStyle::Expanded => OutputStyle::Expanded,
+            CssStyle::Compressed => OutputStyle::Compressed,
+        };
+        let compiler_options = Options::default()
+            .fs(&fs)
+            .style(style)
+            .load_paths(load_paths.unwrap_or_default());
+        let mut rendered =
+            grass::from_path(stylesheet, &compiler_options).map_err(Error::sass(stylesheet))?;
+        let dependencies = fs.read.into_inner();
+
+        let logical = self.output_path(stylesheet);
+        let path = if self.options.fingerprint {
+            fingerprinted(&logical, rendered.as_bytes())
+        } else {
+            logical.clone()
+        };
+        let dir = path.parent().unwrap();
+        let source_map_path = self.source_map_path(&path);
+
+        if let Some(map_path) = &source_map_path {
+            let mut sources = Vec::new();
+            for dependency in &dependencies {
+                let contents = read_to_string(dependency)
+                    .await
+                    .map_err(Error::io(dependency))?;
+                sources.push((dependency.clone(), contents));
+            }
 
-            let file_name = stylesheet.file_name().unwrap();
-            let mut path = Path::new(self.options.output_path).join(file_name);
-            path.set_extension("css");
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            let map = source_map(rendered.as_str(), &file_name, &sources);
+            let map_name = map_path.file_name().unwrap().to_string_lossy();
+            rendered.push_str(&format!("\n/*# sourceMappingURL={} */\n", map_name));
+
+            create_dir_all(dir).await.map_err(Error::io(dir))?;
+            write_file(map_path, map)
+                .await
+                .map_err(Error::io(map_path))?;
+        }
 
-            write(&path, rendered.as_bytes()).await.map_err(Error::Io)?;
+        let changed = read(&path).await.ok().as_deref() != Some(rendered.as_bytes());
+        if changed {
+            create_dir_all(dir).await.map_err(Error::io(dir))?;
+            write_file(&path, rendered.as_bytes())
+                .await
+                .map_err(Error::io(&path))?;
         }
 
-        Ok(())
+        self.options
+            .cache
+            .insert_stylesheet(stylesheet, &path, &dependencies)
+            .await;
+
+        Ok(CompiledStylesheet {
+            output: path,
+            logical,
+            source_map: source_map_path,
+            changed,
+            dependencies,
+        })
+    }
+
+    fn source_map_path(&self, output: &Path) -> Option<PathBuf> {
+        self.options.source_maps.then(|| {
+            let mut path = output.as_os_str().to_owned();
+            path.push(".map");
+            PathBuf::from(path)
+        })
+    }
+
+    fn output_path(&self, stylesheet: &Path) -> PathBuf {
+        let out_dir = Path::new(self.options.output_path);
+        output_path(self.options.input_pattern, stylesheet, out_dir, "css")
+    }
+}
+
+/// Whether `path` is compiled into a stylesheet of its own, rather than only
+/// through the stylesheets that load it
+pub fn is_entry_point(build: &BuildConfig, path: &Path) -> bool {
+    let matches = |pattern: &String| {
+        Pattern::new(pattern)
+            .map(|pattern| pattern.matches_path(path))
+            .unwrap_or_default()
+    };
+
+    match &build.style_entries {
+        Some(entry_points) => entry_points.iter().any(matches),
+        None => matches(&build.style_pattern) && !is_partial(path),
+    }
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with('_'))
+        .unwrap_or_default()
+}
+
+/// Reads from disk like grass normally would, remembering every file read so
+/// that imports can be tracked as dependencies
+#[derive(Debug, Default)]
+struct RecordingFs {
+    read: RefCell<Vec<PathBuf>>,
+}
+
+impl Fs for RecordingFs {
+    fn is_dir(&self, path: &Path) -> bool {
+        StdFs.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        StdFs.is_file(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.read.borrow_mut().push(path.to_path_buf());
+        StdFs.read(path)
     }
 }
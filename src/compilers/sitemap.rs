@@ -0,0 +1,106 @@
+use crate::compilers::{escape_xml, url_path};
+use crate::config::SiteConfig;
+use crate::error::{Error, Result};
+use crate::staging::write_file;
+use chrono::{DateTime, FixedOffset};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// How an HTML page should be listed in the sitemap
+#[derive(Clone, Copy, Debug)]
+pub struct PageMeta {
+    pub lastmod: Option<DateTime<FixedOffset>>,
+    pub sitemap: bool,
+}
+
+impl Default for PageMeta {
+    fn default() -> Self {
+        Self {
+            lastmod: None,
+            sitemap: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WrittenPage {
+    pub path: PathBuf,
+    pub meta: PageMeta,
+}
+
+pub struct SitemapGenerator<'a> {
+    site: &'a SiteConfig,
+}
+
+impl<'a> SitemapGenerator<'a> {
+    pub fn new(site: &'a SiteConfig) -> Self {
+        Self { site }
+    }
+
+    pub async fn generate<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        pages: &BTreeMap<PathBuf, PageMeta>,
+    ) -> Result<Vec<PathBuf>> {
+        let output_path = output_path.as_ref();
+        let mut pages = pages
+            .iter()
+            .filter(|(_, meta)| meta.sitemap)
+            .filter_map(|(path, meta)| {
+                let relative = path.strip_prefix(output_path).ok()?;
+                Some((self.url(relative), meta.lastmod))
+            })
+            .collect::<Vec<_>>();
+        pages.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+        for (url, lastmod) in &pages {
+            xml.push_str("<url>");
+            let _ = write!(xml, "<loc>{}</loc>", escape_xml(url));
+            if let Some(lastmod) = lastmod {
+                let _ = write!(xml, "<lastmod>{}</lastmod>", lastmod.to_rfc3339());
+            }
+            xml.push_str("</url>\n");
+        }
+        xml.push_str("</urlset>\n");
+
+        let sitemap_url = self.url(Path::new("sitemap.xml"));
+        let robots = format!("User-agent: *\nAllow: /\n\nSitemap: {}\n", sitemap_url);
+
+        log::debug!("write sitemap with {} pages", pages.len());
+        let [sitemap_path, robots_path] = self.outputs(output_path);
+        write_file(sitemap_path.as_path(), xml)
+            .await
+            .map_err(Error::io(&sitemap_path))?;
+        write_file(robots_path.as_path(), robots)
+            .await
+            .map_err(Error::io(&robots_path))?;
+
+        Ok(vec![sitemap_path, robots_path])
+    }
+
+    /// Where `generate` writes the sitemap and `robots.txt`
+    pub fn outputs(&self, output_path: &Path) -> [PathBuf; 2] {
+        [
+            output_path.join("sitemap.xml"),
+            output_path.join("robots.txt"),
+        ]
+    }
+
+    /// Builds the absolute URL of an output file. `index.html` files are
+    /// served as their directory
+    fn url(&self, relative: &Path) -> String {
+        let mut path = url_path(relative);
+
+        if path == "index.html" {
+            path.clear();
+        } else if let Some(dir) = path.strip_suffix("/index.html") {
+            path = format!("{}/", dir);
+        }
+
+        self.site.url(path.as_str())
+    }
+}
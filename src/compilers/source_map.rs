@@ -0,0 +1,441 @@
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Builds a version 3 source map from compiled CSS back to the Sass `sources`
+/// it was compiled from, given as paths and contents.
+///
+/// grass doesn't track where output comes from, so each rule is mapped to the
+/// source line its selector is written on, and each declaration to the first
+/// line below that rule setting the same property. Output that can't be placed,
+/// like declarations from mixins, maps to its rule or not at all. Sources are
+/// embedded, since they aren't served next to the CSS.
+pub fn source_map(css: &str, file: &str, sources: &[(PathBuf, String)]) -> String {
+    let lines = sources
+        .iter()
+        .map(|(_, contents)| contents.lines().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    let mut mappings = Mappings::default();
+    let mut cursor = (0, 0);
+    let mut rule = None;
+
+    for statement in statements(css) {
+        let indented = |(source, line): (usize, usize)| {
+            let text: &str = lines[source][line];
+            let indent = text.len() - text.trim_start().len();
+            (source, line, utf16_column(text, indent))
+        };
+
+        let location = if statement.opens_block {
+            rule = find_rule(&lines, cursor, statement.text);
+            if let Some(location) = rule {
+                cursor = location;
+            }
+            rule.map(indented)
+        } else {
+            statement
+                .text
+                .split_once(':')
+                .and_then(|(property, _)| rule.map(|rule| (property.trim(), rule)))
+                .map(|(property, rule)| {
+                    find_declaration(&lines, rule, property).unwrap_or_else(|| indented(rule))
+                })
+        };
+
+        if let Some((source, line, column)) = location {
+            mappings.add(statement.line, statement.column, source, line, column);
+        }
+    }
+
+    let paths = sources
+        .iter()
+        .map(|(path, _)| source_url(path))
+        .collect::<Vec<_>>();
+    let contents = sources
+        .iter()
+        .map(|(_, contents)| contents.as_str())
+        .collect::<Vec<_>>();
+
+    json!({
+        "version": 3,
+        "file": file,
+        "sources": paths,
+        "sourcesContent": contents,
+        "names": [],
+        "mappings": mappings.encoded,
+    })
+    .to_string()
+}
+
+/// A selector, at-rule or declaration in the output, and where it starts
+struct Statement<'a> {
+    line: usize,
+    column: usize,
+    text: &'a str,
+    opens_block: bool,
+}
+
+/// Splits CSS into statements at `{`, `;` and `}`, skipping strings and
+/// comments
+fn statements(css: &str) -> Vec<Statement<'_>> {
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut quote = None;
+    let mut comment = false;
+    let (mut line, mut column) = (0, 0);
+    let mut chars = css.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if comment {
+            if c == '*' && matches!(chars.peek(), Some((_, '/'))) {
+                chars.next();
+                column += 1;
+                comment = false;
+            }
+        } else if let Some(q) = quote {
+            if c == '\\' {
+                // An escaped line break continues the string on the next line
+                match chars.next() {
+                    Some((_, '\n')) => {
+                        line += 1;
+                        column = 0;
+                        continue;
+                    }
+                    Some((_, escaped)) => column += escaped.len_utf16(),
+                    None => {}
+                }
+            } else if c == q {
+                quote = None;
+            }
+        } else {
+            match c {
+                '/' if matches!(chars.peek(), Some((_, '*'))) => comment = true,
+                '"' | '\'' => {
+                    quote = Some(c);
+                    start.get_or_insert((line, column, i));
+                }
+                '{' | ';' | '}' => {
+                    if let Some((line, column, start)) = start.take() {
+                        statements.push(Statement {
+                            line,
+                            column,
+                            text: css[start..i].trim(),
+                            opens_block: c == '{',
+                        });
+                    }
+                }
+                c if c.is_whitespace() => {}
+                _ => {
+                    start.get_or_insert((line, column, i));
+                }
+            }
+        }
+
+        if c == '\n' {
+            line += 1;
+            column = 0;
+        } else {
+            column += c.len_utf16();
+        }
+    }
+
+    statements
+}
+
+/// Finds the source line of a rule, searching onwards from where the previous
+/// rule was found, since output mostly follows source order
+fn find_rule(lines: &[Vec<&str>], cursor: (usize, usize), text: &str) -> Option<(usize, usize)> {
+    if lines.is_empty() {
+        return None;
+    }
+
+    let needles = rule_needles(text);
+    let is_rule = |line: &str| {
+        let line = line.trim();
+        (line.contains('{') || line.ends_with(','))
+            && needles.iter().any(|needle| contains_name(line, needle))
+    };
+
+    let (source, line) = cursor;
+    let order = (source..lines.len())
+        .chain(0..source)
+        .flat_map(|source| {
+            let from = if source == cursor.0 { line } else { 0 };
+            (from..lines[source].len()).map(move |line| (source, line))
+        })
+        .chain((0..line.min(lines[source].len())).map(|line| (source, line)));
+
+    for (source, line) in order {
+        if is_rule(lines[source][line]) {
+            return Some((source, line));
+        }
+    }
+
+    None
+}
+
+/// Text to look for in the source of a rule: at-rules as written, and for
+/// selectors the last compound of the first selector, which nesting leaves
+/// intact, or failing that its pseudo-classes, written after `&`
+fn rule_needles(text: &str) -> Vec<String> {
+    if text.starts_with('@') {
+        return vec![text.to_string()];
+    }
+
+    let selector = text.split(',').next().unwrap_or(text);
+    let compound = selector
+        .split(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .rfind(|part| !part.is_empty())
+        .unwrap_or(selector);
+
+    let mut needles = vec![compound.to_string()];
+    if let Some(i) = compound.find(':').filter(|i| *i > 0) {
+        needles.push(format!("&{}", &compound[i..]));
+    }
+
+    needles
+}
+
+/// Whether `text` contains `name` other than as part of a longer name
+fn contains_name(text: &str, name: &str) -> bool {
+    let is_name = |c: char| c.is_alphanumeric() || matches!(c, '-' | '_');
+    text.match_indices(name).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + name.len()..].chars().next();
+        !before.map(is_name).unwrap_or_default() && !after.map(is_name).unwrap_or_default()
+    })
+}
+
+/// Finds where a property is set within the block of the rule starting at
+/// `rule`, returning its line and column
+fn find_declaration(
+    lines: &[Vec<&str>],
+    (source, rule): (usize, usize),
+    property: &str,
+) -> Option<(usize, usize, usize)> {
+    let mut depth = 0;
+
+    for (line, text) in lines[source].iter().enumerate().skip(rule) {
+        let found = text.match_indices(property).find(|(i, _)| {
+            let before = text[..*i].trim_end();
+            let after = text[i + property.len()..].trim_start();
+            (before.is_empty() || before.ends_with(['{', ';'])) && after.starts_with(':')
+        });
+        if let Some((i, _)) = found {
+            return Some((source, line, utf16_column(text, i)));
+        }
+
+        depth += text.matches('{').count() as isize - text.matches('}').count() as isize;
+        if depth <= 0 && line > rule {
+            break;
+        }
+    }
+
+    None
+}
+
+/// Converts a byte offset into a line to a column. Source maps count columns
+/// in UTF-16 code units
+fn utf16_column(text: &str, offset: usize) -> usize {
+    text[..offset].encode_utf16().count()
+}
+
+/// Names a source by its path from the working directory
+fn source_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// Mappings encoded as they are added, which must be in output order
+#[derive(Default)]
+struct Mappings {
+    encoded: String,
+    line: usize,
+    column: usize,
+    line_has_segments: bool,
+    previous: (usize, usize, usize),
+}
+
+impl Mappings {
+    fn add(
+        &mut self,
+        line: usize,
+        column: usize,
+        source: usize,
+        source_line: usize,
+        source_column: usize,
+    ) {
+        if line > self.line {
+            for _ in self.line..line {
+                self.encoded.push(';');
+            }
+            self.line = line;
+            self.column = 0;
+            self.line_has_segments = false;
+        }
+        if self.line_has_segments {
+            self.encoded.push(',');
+        }
+
+        let (previous_source, previous_line, previous_column) = self.previous;
+        vlq(&mut self.encoded, column as i64 - self.column as i64);
+        vlq(&mut self.encoded, source as i64 - previous_source as i64);
+        vlq(&mut self.encoded, source_line as i64 - previous_line as i64);
+        vlq(
+            &mut self.encoded,
+            source_column as i64 - previous_column as i64,
+        );
+
+        self.column = column;
+        self.line_has_segments = true;
+        self.previous = (source, source_line, source_column);
+    }
+}
+
+/// Appends a base64 VLQ encoded number
+fn vlq(out: &mut String, value: i64) {
+    let mut value = if value < 0 {
+        ((-value) << 1) | 1
+    } else {
+        value << 1
+    };
+
+    loop {
+        let mut digit = value & 31;
+        value >>= 5;
+        if value > 0 {
+            digit |= 32;
+        }
+        out.push(BASE64[digit as usize] as char);
+
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(value: i64) -> String {
+        let mut out = String::new();
+        vlq(&mut out, value);
+        out
+    }
+
+    /// Decodes mappings into absolute `(line, column, source, source line,
+    /// source column)` segments
+    fn decode(mappings: &str) -> Vec<[i64; 5]> {
+        let mut segments = Vec::new();
+        let mut previous = [0; 5];
+
+        for (line, text) in mappings.split(';').enumerate() {
+            previous[1] = 0;
+            for segment in text.split(',').filter(|segment| !segment.is_empty()) {
+                let mut fields = Vec::new();
+                let (mut value, mut shift) = (0, 0);
+                for c in segment.bytes() {
+                    let digit = BASE64.iter().position(|b| *b == c).unwrap() as i64;
+                    value |= (digit & 31) << shift;
+                    shift += 5;
+                    if digit & 32 == 0 {
+                        let magnitude = value >> 1;
+                        fields.push(if value & 1 == 1 {
+                            -magnitude
+                        } else {
+                            magnitude
+                        });
+                        (value, shift) = (0, 0);
+                    }
+                }
+
+                previous[0] = line as i64;
+                for (i, delta) in fields.into_iter().enumerate() {
+                    previous[i + 1] += delta;
+                }
+                segments.push(previous);
+            }
+        }
+
+        segments
+    }
+
+    fn mappings(css: &str, source: &str) -> Vec<[i64; 5]> {
+        let sources = [(PathBuf::from("style.scss"), source.to_string())];
+        let map: serde_json::Value =
+            serde_json::from_str(&source_map(css, "style.css", &sources)).unwrap();
+        decode(map["mappings"].as_str().unwrap())
+    }
+
+    #[test]
+    fn encodes_vlq() {
+        assert_eq!(encoded(0), "A");
+        assert_eq!(encoded(1), "C");
+        assert_eq!(encoded(-1), "D");
+        assert_eq!(encoded(15), "e");
+        assert_eq!(encoded(-15), "f");
+        assert_eq!(encoded(16), "gB");
+        assert_eq!(encoded(-16), "hB");
+        assert_eq!(encoded(31), "+B");
+        assert_eq!(encoded(1000), "w+B");
+    }
+
+    #[test]
+    fn encodes_segments_relative_to_the_previous_one() {
+        let mut mappings = Mappings::default();
+        mappings.add(0, 0, 0, 0, 0);
+        mappings.add(0, 5, 0, 1, 2);
+        mappings.add(2, 2, 1, 0, 0);
+
+        assert_eq!(mappings.encoded, "AAAA,KACE;;ECDF");
+    }
+
+    #[test]
+    fn maps_nested_rules() {
+        let source = ".nav {\n  color: red;\n  a {\n    color: blue;\n  }\n}\n";
+        let css = ".nav {\n  color: red;\n}\n.nav a {\n  color: blue;\n}\n";
+
+        assert_eq!(
+            mappings(css, source),
+            [
+                [0, 0, 0, 0, 0],
+                [1, 2, 0, 1, 2],
+                [3, 0, 0, 2, 2],
+                [4, 2, 0, 3, 4],
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_repeated_selectors_in_source_order() {
+        let source = ".a { color: red; }\n.b { color: green; }\n.a { margin: 0; }\n";
+        let css = ".a {\n  color: red;\n}\n\n.b {\n  color: green;\n}\n\n.a {\n  margin: 0;\n}\n";
+
+        assert_eq!(
+            mappings(css, source),
+            [
+                [0, 0, 0, 0, 0],
+                [1, 2, 0, 0, 5],
+                [4, 0, 0, 1, 0],
+                [5, 2, 0, 1, 5],
+                [8, 0, 0, 2, 0],
+                [9, 2, 0, 2, 5],
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_columns_in_utf16_code_units() {
+        let source = "/* 😀 */ .café { color: red; }\n";
+        let css = "/* 😀 */.café{color:red}";
+
+        assert_eq!(mappings(css, source), [[0, 8, 0, 0, 0], [0, 14, 0, 0, 17]]);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::args::BuildMode;
+use crate::build_cache::digest;
+use crate::compilers::{
+    page_count, paginate, FrontMatter, HandlebarsCompiler, PageMeta, PageUrls, Paginator,
+    PlannedPost,
+};
+use crate::config::{TaxonomyConfig, CONFIG_FILE};
+use crate::error::{Error, Result};
+use crate::post_cache::{Post, PostCache};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct Term {
+    pub name: String,
+    pub slug: String,
+    pub url: String,
+}
+
+impl Term {
+    fn new(taxonomy: &str, name: &str) -> Self {
+        let slug = slugify(name);
+        let url = format!("/{}/{}/", taxonomy, slug);
+        let name = name.to_string();
+
+        Self { name, slug, url }
+    }
+}
+
+/// Reads the terms a post lists under each configured taxonomy. Terms may be
+/// given as a single string or an array of strings
+pub fn post_terms(taxonomies: &[TaxonomyConfig], fm: &FrontMatter) -> BTreeMap<String, Vec<Term>> {
+    taxonomies
+        .iter()
+        .map(|taxonomy| {
+            let names = match fm.get(taxonomy.name.as_str()) {
+                Some(toml::Value::String(name)) => vec![name.as_str()],
+                Some(toml::Value::Array(names)) => {
+                    names.iter().filter_map(|n| n.as_str()).collect()
+                }
+                _ => Vec::new(),
+            };
+            let terms = names
+                .into_iter()
+                .map(|name| Term::new(taxonomy.name.as_str(), name))
+                .collect();
+
+            (taxonomy.name.clone(), terms)
+        })
+        .collect()
+}
+
+/// Lowercases a term name and joins its words with `-`. Names without any
+/// letters or digits would have an empty slug, so get one from their hash
+fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        format!("term-{}", &digest([name])[..8])
+    } else {
+        slug.to_string()
+    }
+}
+
+pub struct TaxonomyCompiler<'a> {
+    build_mode: BuildMode,
+    taxonomies: &'a [TaxonomyConfig],
+}
+
+impl<'a> TaxonomyCompiler<'a> {
+    pub fn new(build_mode: BuildMode, taxonomies: &'a [TaxonomyConfig]) -> Self {
+        Self {
+            build_mode,
+            taxonomies,
+        }
+    }
+
+    pub async fn compile<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        post_cache: &PostCache,
+        handlebars: &HandlebarsCompiler<'_>,
+    ) -> Result<()> {
+        let output_path = output_path.as_ref();
+
+        for taxonomy in self.taxonomies {
+            let terms = collect_terms(taxonomy.name.as_str(), post_cache);
+            let taxonomy_dir = output_path.join(taxonomy.name.as_str());
+
+            for term in &terms {
+                let term_dir = taxonomy_dir.join(term.term.slug.as_str());
+                let urls = PageUrls {
+                    first_url: term.term.url.as_str(),
+                    base_url: term.term.url.as_str(),
+                };
+                let per_page = taxonomy.paginate.unwrap_or(term.posts.len());
+
+                for paginator in paginate(term.posts.as_slice(), per_page, &urls) {
+                    let path = if paginator.page == 1 {
+                        term_dir.join("index.html")
+                    } else {
+                        urls.path(output_path, paginator.page)
+                    };
+
+                    log::debug!(
+                        "render {} term {:?} page {} -> {:?}",
+                        taxonomy.name,
+                        term.term.name,
+                        paginator.page,
+                        path
+                    );
+
+                    let context = TermContext {
+                        dev_mode: !self.build_mode.is_release(),
+                        mode: self.build_mode.to_string(),
+                        taxonomy: taxonomy.name.as_str(),
+                        term: &term.term,
+                        posts: term.posts.as_slice(),
+                        paginator: taxonomy.paginate.map(|_| paginator),
+                    };
+
+                    write_page(handlebars, taxonomy.template.as_str(), &context, &path).await?;
+                }
+            }
+
+            if let Some(template) = taxonomy.index_template.as_deref() {
+                let path = taxonomy_dir.join("index.html");
+                let context = TermsIndexContext {
+                    dev_mode: !self.build_mode.is_release(),
+                    mode: self.build_mode.to_string(),
+                    taxonomy: taxonomy.name.as_str(),
+                    terms: terms.as_slice(),
+                };
+
+                log::debug!("render {} index -> {:?}", taxonomy.name, path);
+                write_page(handlebars, template, &context, &path).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The pages `compile` writes for `posts`. Names differing only in case
+    /// are the same term, but other names sharing a slug would share a page,
+    /// so are an error
+    pub fn outputs(&self, output_path: &Path, posts: &[PlannedPost]) -> Result<Vec<PathBuf>> {
+        let mut outputs = Vec::new();
+
+        for taxonomy in self.taxonomies {
+            let mut terms: BTreeMap<&str, (&Term, &Path, usize)> = BTreeMap::new();
+            for post in posts {
+                let post_terms = post.taxonomies.get(taxonomy.name.as_str());
+                for term in post_terms.into_iter().flatten() {
+                    let (first, source, count) =
+                        terms
+                            .entry(term.slug.as_str())
+                            .or_insert((term, post.source.as_path(), 0));
+
+                    if first.name.to_lowercase() != term.name.to_lowercase() {
+                        return Err(Error::DuplicateOutput {
+                            output: PathBuf::from(taxonomy.name.as_str())
+                                .join(term.slug.as_str())
+                                .join("index.html"),
+                            first: source.to_path_buf(),
+                            second: post.source.clone(),
+                        });
+                    }
+                    *count += 1;
+                }
+            }
+
+            let taxonomy_dir = output_path.join(taxonomy.name.as_str());
+            for (slug, (term, _, count)) in terms {
+                let urls = PageUrls {
+                    first_url: term.url.as_str(),
+                    base_url: term.url.as_str(),
+                };
+                let pages = page_count(count, taxonomy.paginate.unwrap_or(count));
+
+                outputs.push(taxonomy_dir.join(slug).join("index.html"));
+                outputs.extend((2..=pages).map(|page| urls.path(output_path, page)));
+            }
+
+            if taxonomy.index_template.is_some() {
+                outputs.push(taxonomy_dir.join("index.html"));
+            }
+        }
+
+        Ok(outputs)
+    }
+}
+
+async fn write_page<S: Serialize>(
+    handlebars: &HandlebarsCompiler<'_>,
+    template: &str,
+    context: &S,
+    path: &Path,
+) -> Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).await.map_err(Error::io(dir))?;
+
+    handlebars
+        .render_to_write(
+            template,
+            context,
+            path,
+            PageMeta::default(),
+            Path::new(CONFIG_FILE),
+        )
+        .await
+}
+
+/// Groups posts by each of their terms, ordered by term name. Posts keep the
+/// order of the post cache
+fn collect_terms<'a>(taxonomy: &str, post_cache: &'a PostCache) -> Vec<TermPosts<'a>> {
+    let mut terms: BTreeMap<&str, TermPosts> = BTreeMap::new();
+
+    for post in post_cache.posts() {
+        let post_terms = post.taxonomies.get(taxonomy).into_iter().flatten();
+        for term in post_terms {
+            let entry = terms
+                .entry(term.slug.as_str())
+                .or_insert_with(|| TermPosts {
+                    term: term.clone(),
+                    count: 0,
+                    posts: Vec::new(),
+                });
+
+            entry.count += 1;
+            entry.posts.push(post);
+        }
+    }
+
+    terms.into_values().collect()
+}
+
+#[derive(Serialize)]
+struct TermPosts<'a> {
+    #[serde(flatten)]
+    term: Term,
+    count: usize,
+    posts: Vec<&'a Post>,
+}
+
+#[derive(Serialize)]
+struct TermContext<'a> {
+    dev_mode: bool,
+    mode: String,
+    taxonomy: &'a str,
+    term: &'a Term,
+    posts: &'a [&'a Post],
+    paginator: Option<Paginator<'a, &'a Post>>,
+}
+
+#[derive(Serialize)]
+struct TermsIndexContext<'a> {
+    dev_mode: bool,
+    mode: String,
+    taxonomy: &'a str,
+    terms: &'a [TermPosts<'a>],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugifies_words() {
+        assert_eq!(slugify("Rust"), "rust");
+        assert_eq!(
+            slugify("  Static  Site, Generators! "),
+            "static-site-generators"
+        );
+        assert_eq!(slugify("C++"), "c");
+    }
+
+    #[test]
+    fn falls_back_for_empty_slugs() {
+        let slug = slugify("!!!");
+        assert!(slug.starts_with("term-") && slug.len() == 13);
+        assert_ne!(slug, slugify("???"));
+    }
+}
@@ -1,12 +1,24 @@
+use crate::args::BuildMode;
+use crate::compilers::MinifyOptions;
 use crate::error::{Error, Result};
-use std::{fs, path::Path};
+use std::{cmp::Ordering, fs, path::Path, str::FromStr};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+/// The config file, relative to the working directory
+pub const CONFIG_FILE: &str = "config.toml";
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub build: BuildConfig,
-    pub http: HttpConfig,
+    pub feeds: Option<FeedsConfig>,
+    pub html: Option<HtmlConfig>,
+    pub http: Option<HttpConfig>,
+    pub params: Option<toml::Table>,
+    pub posts: Option<PostsConfig>,
+    pub site: Option<SiteConfig>,
+    pub styles: Option<StylesConfig>,
+    pub taxonomies: Option<Vec<TaxonomyConfig>>,
     pub watch: WatchConfig,
 }
 
@@ -17,13 +29,157 @@ pub struct BuildConfig {
     pub partials_pattern: String,
     pub post_pattern: String,
     pub style_pattern: String,
-    pub copy: Option<Vec<String>>,
+    pub style_entries: Option<Vec<String>>,
+    pub style_load_paths: Option<Vec<String>>,
+    pub copy: Option<Vec<CopyConfig>>,
+    pub copy_exclude: Option<Vec<String>>,
+    pub fingerprint: Option<Vec<String>>,
+    pub keep_previous: Option<bool>,
+}
+
+/// Files or directories to copy into `out_dir`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CopyConfig {
+    /// Copied to the same path beneath `out_dir`
+    Pattern(String),
+    /// Copied to `to` beneath `out_dir`, leaving out the leading directories
+    /// of `from`
+    Mapped { from: String, to: String },
+}
+
+impl CopyConfig {
+    /// The pattern matching the files to copy
+    pub fn source(&self) -> &str {
+        match self {
+            CopyConfig::Pattern(pattern) => pattern,
+            CopyConfig::Mapped { from, .. } => from,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FeedsConfig {
+    pub content: Option<FeedContent>,
+    pub limit: Option<usize>,
+    pub rss: Option<FeedConfig>,
+    pub atom: Option<FeedConfig>,
+    pub json: Option<FeedConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FeedConfig {
+    pub content: Option<FeedContent>,
+    pub limit: Option<usize>,
+    pub path: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    #[default]
+    Full,
+    Summary,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HtmlConfig {
+    pub minify: Option<bool>,
+    pub minify_css: Option<bool>,
+    pub minify_js: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct HttpConfig {
-    pub command: String,
+    pub command: Option<String>,
     pub args: Option<Vec<String>>,
+    pub port: Option<u16>,
+    pub websocket_port: Option<u16>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PostsConfig {
+    pub sort_by: Option<String>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    pub fn apply(&self, ordering: Ordering) -> Ordering {
+        match self {
+            Self::Asc => ordering,
+            Self::Desc => ordering.reverse(),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "asc" => Ok(Self::Asc),
+            "desc" => Ok(Self::Desc),
+            _ => Err(format!("unknown sort order `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SiteConfig {
+    pub title: String,
+    pub base_url: String,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    pub social: Option<toml::Table>,
+}
+
+impl SiteConfig {
+    /// The absolute URL of a path on the site
+    pub fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+}
+
+/// The `site` object available to every template: the `[site]` table with the
+/// free-form `[params]` table nested under `params`
+#[derive(Debug, Serialize)]
+pub struct SiteContext<'a> {
+    #[serde(flatten)]
+    pub site: Option<&'a SiteConfig>,
+    pub params: Option<&'a toml::Table>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StylesConfig {
+    pub output_style: Option<CssStyle>,
+    pub source_maps: Option<bool>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CssStyle {
+    Expanded,
+    Compressed,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TaxonomyConfig {
+    pub name: String,
+    pub template: String,
+    pub index_template: Option<String>,
+    pub paginate: Option<usize>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -34,7 +190,79 @@ pub struct WatchConfig {
 impl Config {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
         let path = path.as_ref();
-        let text = fs::read_to_string(path).map_err(Error::Io)?;
-        toml::from_str(text.as_str()).map_err(Error::Toml)
+        let text = fs::read_to_string(path).map_err(Error::io(path))?;
+        toml::from_str(text.as_str()).map_err(Error::toml(path, text.as_str(), 1))
+    }
+
+    /// Replaces `site.base_url`, e.g. for preview deployments
+    pub fn override_base_url(&mut self, base_url: &str) -> Result<()> {
+        let site = self.site.as_mut().ok_or(Error::MissingConfig("site"))?;
+        site.base_url = base_url.to_string();
+        Ok(())
+    }
+
+    pub fn site_context(&self) -> SiteContext<'_> {
+        SiteContext {
+            site: self.site.as_ref(),
+            params: self.params.as_ref(),
+        }
+    }
+
+    pub fn websocket_port(&self) -> u16 {
+        self.http
+            .as_ref()
+            .and_then(|http| http.websocket_port)
+            .unwrap_or(8081)
+    }
+
+    pub fn taxonomies(&self) -> &[TaxonomyConfig] {
+        self.taxonomies.as_deref().unwrap_or_default()
+    }
+
+    pub fn post_sort(&self) -> (&str, SortOrder) {
+        let posts = self.posts.as_ref();
+        let sort_by = posts
+            .and_then(|p| p.sort_by.as_deref())
+            .unwrap_or("published");
+        let order = posts.and_then(|p| p.order).unwrap_or_default();
+
+        (sort_by, order)
+    }
+
+    /// How CSS is written: compressed in release builds and expanded
+    /// otherwise, unless `styles.output_style` says differently
+    pub fn css_style(&self, build_mode: BuildMode) -> CssStyle {
+        let default = match build_mode {
+            BuildMode::Development => CssStyle::Expanded,
+            BuildMode::Release => CssStyle::Compressed,
+        };
+
+        self.styles
+            .as_ref()
+            .and_then(|styles| styles.output_style)
+            .unwrap_or(default)
+    }
+
+    /// Whether stylesheets get source maps, by default only in development
+    pub fn source_maps(&self, build_mode: BuildMode) -> bool {
+        self.styles
+            .as_ref()
+            .and_then(|styles| styles.source_maps)
+            .unwrap_or(!build_mode.is_release())
+    }
+
+    /// How HTML is minified, which happens only in release builds and unless
+    /// `html.minify` is false
+    pub fn html_minify(&self, build_mode: BuildMode) -> Option<MinifyOptions> {
+        let html = self.html.as_ref();
+        let minify = html.and_then(|html| html.minify).unwrap_or(true);
+        if !build_mode.is_release() || !minify {
+            return None;
+        }
+
+        Some(MinifyOptions {
+            css: html.and_then(|html| html.minify_css).unwrap_or_default(),
+            js: html.and_then(|html| html.minify_js).unwrap_or_default(),
+        })
     }
 }
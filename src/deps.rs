@@ -0,0 +1,245 @@
+use crate::compilers::{glob_base, is_entry_point};
+use crate::config::Config;
+use glob::Pattern;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::env;
+use std::path::{Component, Path, PathBuf};
+
+/// Records which inputs each output was built from, so that a change to one
+/// input only rebuilds the outputs that depend on it.
+///
+/// Template dependencies are found by scanning sources for the names of known
+/// partials. This over-approximates (a partial name used as plain text counts
+/// as a reference), which costs an occasional extra render but never misses a
+/// dependency on a statically named partial.
+#[derive(Default)]
+pub struct DependencyGraph {
+    partials: HashMap<String, HashSet<String>>,
+    partial_files: HashMap<PathBuf, String>,
+    pages: HashMap<PathBuf, HashSet<String>>,
+    stylesheets: HashMap<PathBuf, HashSet<PathBuf>>,
+}
+
+/// What needs to be redone after some inputs changed
+#[derive(Debug, Default)]
+pub struct RebuildPlan {
+    /// Something changed that the graph doesn't know about
+    pub full: bool,
+    /// Stylesheet entry points to recompile
+    pub stylesheets: BTreeSet<PathBuf>,
+    /// Partial files to register again
+    pub partials: BTreeSet<PathBuf>,
+    /// Partial names whose rendering may have changed
+    pub templates: HashSet<String>,
+    /// Post sources to render again
+    pub posts: BTreeSet<PathBuf>,
+    /// Page sources to render again
+    pub pages: BTreeSet<PathBuf>,
+    /// Whether the set of posts or their contents changed, which affects every
+    /// post listing
+    pub listings: bool,
+    /// Whether copied files changed
+    pub copy: bool,
+}
+
+impl RebuildPlan {
+    /// Whether a template that renders through `template` must be rendered
+    /// again
+    pub fn uses_template(&self, graph: &DependencyGraph, template: &str) -> bool {
+        graph
+            .closure(&HashSet::from([template.to_string()]))
+            .iter()
+            .any(|name| self.templates.contains(name))
+    }
+}
+
+impl DependencyGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_partial<P: AsRef<Path>>(&mut self, path: P, name: &str, source: &str) {
+        self.partials.insert(name.to_string(), tokens(source));
+        self.partial_files
+            .insert(normalize(path.as_ref()), name.to_string());
+    }
+
+    pub fn remove_partial<P: AsRef<Path>>(&mut self, path: P) -> Option<String> {
+        let name = self.partial_files.remove(&normalize(path.as_ref()))?;
+        self.partials.remove(&name);
+        Some(name)
+    }
+
+    pub fn add_page<P: AsRef<Path>>(&mut self, path: P, source: &str) {
+        self.pages.insert(normalize(path.as_ref()), tokens(source));
+    }
+
+    pub fn remove_page<P: AsRef<Path>>(&mut self, path: P) {
+        self.pages.remove(&normalize(path.as_ref()));
+    }
+
+    pub fn add_stylesheet<P: AsRef<Path>>(&mut self, entry: P, loaded: &[PathBuf]) {
+        let loaded = loaded.iter().map(|path| normalize(path)).collect();
+        self.stylesheets.insert(normalize(entry.as_ref()), loaded);
+    }
+
+    pub fn remove_stylesheet<P: AsRef<Path>>(&mut self, entry: P) {
+        self.stylesheets.remove(&normalize(entry.as_ref()));
+    }
+
+    /// Every partial reachable from a set of template tokens
+    fn closure(&self, tokens: &HashSet<String>) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut pending = tokens
+            .iter()
+            .filter(|token| self.partials.contains_key(*token))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+
+            if let Some(refs) = self.partials.get(&name) {
+                let refs = refs.iter().filter(|r| self.partials.contains_key(*r));
+                pending.extend(refs.cloned());
+            }
+        }
+
+        seen
+    }
+
+    /// Whether a page lists posts, either directly, through pagination or
+    /// through a partial
+    fn reads_posts(&self, tokens: &HashSet<String>) -> bool {
+        tokens.contains("posts")
+            || tokens.contains("paginate")
+            || self
+                .closure(tokens)
+                .iter()
+                .any(|name| self.partials[name].contains("posts"))
+    }
+
+    pub fn plan(&self, config: &Config, changed: &[PathBuf]) -> RebuildPlan {
+        let mut plan = RebuildPlan::default();
+        let copy_entries = config.build.copy.as_deref().unwrap_or_default();
+
+        for path in changed {
+            let path = normalize(path);
+            let relative = relative(&path);
+            let matches = |pattern: &str| {
+                Pattern::new(pattern)
+                    .map(|p| p.matches_path(&relative))
+                    .unwrap_or_default()
+            };
+            let mut known = false;
+
+            for (entry, loaded) in &self.stylesheets {
+                if *entry == path || loaded.contains(&path) {
+                    plan.stylesheets.insert(entry.clone());
+                    known = true;
+                }
+            }
+
+            if is_entry_point(&config.build, &relative) {
+                plan.stylesheets.insert(path.clone());
+                known = true;
+            } else if matches(config.build.style_pattern.as_str()) {
+                // A partial nothing loads yet
+                known = true;
+            }
+
+            if matches(config.build.partials_pattern.as_str()) {
+                if let Some(name) = self.partial_files.get(&path) {
+                    plan.templates.insert(name.clone());
+                }
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    plan.templates.insert(name.to_string());
+                }
+                plan.partials.insert(path.clone());
+                known = true;
+            }
+
+            if matches(config.build.post_pattern.as_str()) {
+                plan.posts.insert(path.clone());
+                plan.listings = true;
+                known = true;
+            }
+
+            if matches(config.build.page_pattern.as_str()) {
+                plan.pages.insert(path.clone());
+                known = true;
+            }
+
+            if copy_entries.iter().any(|entry| {
+                let pattern = entry.source();
+                let base = glob_base(pattern);
+                matches(pattern)
+                    || relative.starts_with(pattern.trim_end_matches('/'))
+                    || (!base.as_os_str().is_empty() && relative.starts_with(base))
+            }) {
+                plan.copy = true;
+                known = true;
+            }
+
+            if !known {
+                plan.full = true;
+            }
+        }
+
+        for (page, tokens) in &self.pages {
+            let uses_changed_partial = self
+                .closure(tokens)
+                .iter()
+                .any(|name| plan.templates.contains(name));
+
+            if uses_changed_partial || (plan.listings && self.reads_posts(tokens)) {
+                plan.pages.insert(page.clone());
+            }
+        }
+
+        plan
+    }
+}
+
+/// Splits a template into the identifiers it mentions
+fn tokens(source: &str) -> HashSet<String> {
+    source
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Makes a path absolute and removes `.` and `..` components, so paths
+/// reported by the file watcher compare equal to paths found by globbing
+pub fn normalize(path: &Path) -> PathBuf {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().unwrap_or_default().join(path)
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// A normalized path relative to the working directory, or as it is if it lies
+/// outside of it
+pub fn relative(path: &Path) -> PathBuf {
+    let cwd = normalize(Path::new("."));
+    path.strip_prefix(&cwd)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
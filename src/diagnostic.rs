@@ -0,0 +1,70 @@
+use crate::error::Error;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+
+/// Renders an error for the terminal: its message, where it happened and,
+/// when the line is known, the offending line of source. Collected build
+/// errors are grouped by file and followed by a count
+pub fn render(error: &Error) -> String {
+    let mut out = String::new();
+
+    match error {
+        Error::Build(errors) => {
+            let mut by_file = BTreeMap::<_, Vec<_>>::new();
+            for error in errors {
+                by_file
+                    .entry(error.location().file)
+                    .or_default()
+                    .push(error);
+            }
+
+            for error in by_file.values().flatten() {
+                write_diagnostic(&mut out, error);
+            }
+
+            let _ = writeln!(
+                out,
+                "{} error{} in {} file{}",
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" },
+                by_file.len(),
+                if by_file.len() == 1 { "" } else { "s" },
+            );
+        }
+        _ => write_diagnostic(&mut out, error),
+    }
+
+    out
+}
+
+fn write_diagnostic(out: &mut String, error: &Error) {
+    let location = error.location();
+    let _ = writeln!(out, "error: {}", error);
+
+    if let Some(file) = &location.file {
+        let _ = match (location.line, location.column) {
+            (Some(line), Some(column)) => writeln!(out, "  --> {}:{}:{}", file, line, column),
+            (Some(line), None) => writeln!(out, "  --> {}:{}", file, line),
+            _ => writeln!(out, "  --> {}", file),
+        };
+
+        let source = location.line.and_then(|line| {
+            let contents = fs::read_to_string(file).ok()?;
+            let text = contents.lines().nth(line.checked_sub(1)?)?.to_string();
+            Some((line, text))
+        });
+
+        if let Some((line, text)) = source {
+            let gutter = line.to_string().len();
+            let _ = writeln!(out, "{:gutter$} |", "");
+            let _ = writeln!(out, "{} | {}", line, text);
+            if let Some(column) = location.column {
+                let indent = column.saturating_sub(1);
+                let _ = writeln!(out, "{:gutter$} | {:indent$}^", "", "");
+            }
+        }
+    }
+
+    out.push('\n');
+}
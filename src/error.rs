@@ -1,35 +1,274 @@
-use std::{fmt::Display, io};
+use std::{
+    error::Error as StdError,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
 
 use glob::{GlobError, PatternError};
-use handlebars::RenderError;
+use grass::ErrorKind as SassErrorKind;
+use handlebars::{RenderError, TemplateError};
+use serde::Serialize;
+
+use crate::config::CONFIG_FILE;
 
 #[derive(Debug)]
 pub enum Error {
-    Toml(toml::de::Error),
+    /// Every failure of a build that kept going after the first
+    Build(Vec<Error>),
+    /// Two inputs would be written to the same output, relative to `out_dir`
+    DuplicateOutput {
+        output: PathBuf,
+        first: PathBuf,
+        second: PathBuf,
+    },
     Glob(GlobError),
-    HandlebarsError(RenderError),
-    Io(io::Error),
-    MarkdownError(String),
-    MissingFrontMatter,
-    Pattern(PatternError),
-    Sass(Box<grass::Error>),
+    Handlebars {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        error: Box<RenderError>,
+    },
+    /// A partial's file name can't be used as a partial name
+    InvalidPartialName(PathBuf),
+    Io(PathBuf, io::Error),
+    /// The development server couldn't listen on an address
+    Listen(String, io::Error),
+    Markdown(PathBuf, String),
+    MissingConfig(&'static str),
+    /// A required front matter field is missing or has the wrong type
+    MissingField(PathBuf, &'static str),
+    MissingFrontMatter(PathBuf),
+    /// A socket of the development server failed
+    Network(io::Error),
+    Pattern(String, PatternError),
+    Sass(PathBuf, Box<grass::Error>),
+    Toml {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        error: Box<toml::de::Error>,
+    },
+    /// Changes couldn't be watched, at the given path if one was at fault
+    Watch(Option<PathBuf>, notify::Error),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Glob(error) => write!(f, "{}", error),
-            Self::HandlebarsError(error) => write!(f, "{}", error),
-            Self::Io(error) => write!(f, "{}", error),
-            Self::MarkdownError(error) => write!(f, "{}", error),
-            Self::MissingFrontMatter => write!(f, "missing front matter"),
-            Self::Pattern(error) => write!(f, "{}", error),
-            Self::Sass(error) => write!(f, "{}", error),
-            Self::Toml(error) => write!(f, "{}", error),
+            Self::Build(errors) => {
+                write!(f, "{} errors", errors.len())?;
+                for error in errors {
+                    match error.location().file {
+                        Some(file) => write!(f, "\n{}: {}", file, error)?,
+                        None => write!(f, "\n{}", error)?,
+                    }
+                }
+                Ok(())
+            }
+            Self::DuplicateOutput { output, first, .. } => {
+                let (output, first) = (output.display(), first.display());
+                write!(f, "`{}` is also written by `{}`", output, first)
+            }
+            Self::Glob(error) => write!(f, "{}", error.error()),
+            Self::Handlebars { error, .. } => {
+                let cause = error
+                    .source()
+                    .and_then(|cause| cause.downcast_ref::<TemplateError>());
+                match cause {
+                    Some(cause) => write!(
+                        f,
+                        "{}: {}",
+                        error.desc.trim_end_matches('.'),
+                        cause.reason()
+                    ),
+                    None => write!(f, "{}", error.desc),
+                }
+            }
+            Self::InvalidPartialName(_) => write!(f, "partial file names must be valid UTF-8"),
+            Self::Io(_, error) => write!(f, "{}", error),
+            Self::Listen(address, error) => {
+                write!(f, "unable to listen on {}: {}", address, error)
+            }
+            Self::Markdown(_, error) => {
+                let message = error
+                    .split_once(": ")
+                    .filter(|(position, _)| parse_position(position).is_some())
+                    .map(|(_, message)| message)
+                    .unwrap_or(error);
+                write!(f, "{}", message)
+            }
+            Self::MissingConfig(section) => write!(f, "missing [{}] config section", section),
+            Self::MissingField(_, field) => {
+                write!(
+                    f,
+                    "front matter field `{}` is missing or not a string",
+                    field
+                )
+            }
+            Self::MissingFrontMatter(_) => write!(f, "missing front matter"),
+            Self::Network(error) => write!(f, "{}", error),
+            Self::Pattern(pattern, error) => write!(f, "invalid pattern `{}`: {}", pattern, error),
+            Self::Sass(_, error) => match error.as_ref().clone().kind() {
+                SassErrorKind::ParseError { message, .. } => write!(f, "{}", message),
+                _ => write!(f, "{}", error),
+            },
+            Self::Toml { error, .. } if error.message().is_empty() => write!(f, "invalid TOML"),
+            Self::Toml { error, .. } => write!(f, "{}", error.message()),
+            Self::Watch(_, error) => write!(f, "unable to watch for changes: {}", error),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// Where an error occurred, as far as it is known
+#[derive(Debug, Default, Serialize)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Location {
+    fn file<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            file: Some(path.as_ref().to_string_lossy().into_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn at<P: AsRef<Path>>(path: P, line: Option<usize>, column: Option<usize>) -> Self {
+        Self {
+            line,
+            column,
+            ..Self::file(path)
+        }
+    }
+}
+
+impl Error {
+    pub fn io<P: AsRef<Path>>(path: P) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |error| Self::Io(path, error)
+    }
+
+    pub fn pattern(pattern: &str) -> impl FnOnce(PatternError) -> Self + '_ {
+        move |error| Self::Pattern(pattern.to_string(), error)
+    }
+
+    pub fn sass<P: AsRef<Path>>(path: P) -> impl FnOnce(Box<grass::Error>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |error| Self::Sass(path, error)
+    }
+
+    /// Locates a TOML error in `source`, which starts on `first_line` of the
+    /// file at `path`
+    pub fn toml<'a, P: AsRef<Path>>(
+        path: P,
+        source: &'a str,
+        first_line: usize,
+    ) -> impl FnOnce(toml::de::Error) -> Self + 'a {
+        let path = path.as_ref().to_path_buf();
+        move |error| {
+            let start = error.span().map(|span| span.start.min(source.len()));
+            let before = start.map(|start| &source[..start]);
+            let line = before.map(|before| before.matches('\n').count() + first_line);
+            let column = before.map(|before| {
+                before
+                    .rsplit_once('\n')
+                    .map(|(_, last)| last)
+                    .unwrap_or(before)
+                    .chars()
+                    .count()
+                    + 1
+            });
+
+            Self::Toml {
+                path,
+                line,
+                column,
+                error: Box::new(error),
+            }
+        }
+    }
+
+    pub fn location(&self) -> Location {
+        match self {
+            Self::Build(errors) => errors
+                .first()
+                .map(|error| error.location())
+                .unwrap_or_default(),
+            Self::DuplicateOutput { second, .. } => Location::file(second),
+            Self::Glob(error) => Location::file(error.path()),
+            Self::Handlebars {
+                path, line, column, ..
+            }
+            | Self::Toml {
+                path, line, column, ..
+            } => Location::at(path, *line, *column),
+            Self::InvalidPartialName(path)
+            | Self::Io(path, _)
+            | Self::MissingField(path, _)
+            | Self::MissingFrontMatter(path) => Location::file(path),
+            Self::Markdown(path, error) => {
+                let position = error
+                    .split_once(": ")
+                    .and_then(|(position, _)| parse_position(position));
+                Location::at(
+                    path,
+                    position.map(|(line, _)| line),
+                    position.map(|(_, column)| column),
+                )
+            }
+            Self::MissingConfig(_) | Self::Pattern(..) => Location::file(CONFIG_FILE),
+            Self::Listen(..) | Self::Network(_) => Location::default(),
+            Self::Watch(path, _) => path.as_ref().map(Location::file).unwrap_or_default(),
+            Self::Sass(path, error) => match error.as_ref().clone().kind() {
+                SassErrorKind::ParseError { loc, .. } => Location::at(
+                    loc.file.name(),
+                    Some(loc.begin.line + 1),
+                    Some(loc.begin.column + 1),
+                ),
+                _ => Location::file(path),
+            },
+        }
+    }
+
+    /// The process exit code for this kind of error. A build that collected
+    /// errors of different kinds exits with 1
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Build(errors) => {
+                let mut codes = errors.iter().map(Self::exit_code);
+                let first = codes.next().unwrap_or(1);
+                if codes.all(|code| code == first) {
+                    first
+                } else {
+                    1
+                }
+            }
+            Self::DuplicateOutput { .. }
+            | Self::MissingConfig(_)
+            | Self::Pattern(..)
+            | Self::Watch(..) => 2,
+            Self::Toml { path, .. } if path == Path::new(CONFIG_FILE) => 2,
+            Self::Markdown(..)
+            | Self::MissingField(..)
+            | Self::MissingFrontMatter(_)
+            | Self::Toml { .. } => 3,
+            Self::Handlebars { .. } | Self::InvalidPartialName(_) => 4,
+            Self::Sass(..) => 5,
+            Self::Glob(_) | Self::Io(..) => 6,
+            Self::Listen(..) | Self::Network(_) => 7,
+        }
+    }
+}
+
+/// Parses the `line:column` prefix of Markdown errors
+fn parse_position(position: &str) -> Option<(usize, usize)> {
+    let (line, column) = position.split_once(':')?;
+    Some((line.parse().ok()?, column.parse().ok()?))
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
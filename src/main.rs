@@ -1,31 +1,51 @@
 mod args;
+mod build_cache;
 mod cmd;
 mod compilers;
 mod config;
+mod deps;
+mod diagnostic;
 mod error;
+mod manifest;
+mod parallel;
 mod post_cache;
+mod staging;
 
 use crate::args::{Args, Command};
-use crate::config::Config;
+use crate::config::{Config, CONFIG_FILE};
+use crate::error::Result;
 use clap::Parser;
+use std::process;
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() {
+fn main() {
     env_logger::init();
 
     let args = Args::parse();
     log::debug!("{:?}", args);
 
-    let config = Config::load("config.toml").expect("Unable to read config file");
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(args.jobs())
+        .enable_all()
+        .build()
+        .expect("Unable to start runtime")
+        .block_on(run(args));
+
+    if let Err(error) = result {
+        eprint!("{}", diagnostic::render(&error));
+        process::exit(error.exit_code());
+    }
+}
+
+async fn run(args: Args) -> Result<()> {
+    let mut config = Config::load(CONFIG_FILE)?;
+    if let Some(base_url) = args.base_url.as_deref() {
+        config.override_base_url(base_url)?;
+    }
     log::debug!("{:?}", config);
 
-    let result = match args.command {
-        Command::Build => cmd::build(&args, &config).await,
-        Command::Clean => Ok(cmd::clean(&args, &config).await),
+    match args.command {
+        Command::Build => cmd::build(&args, &config).await.map(|_| ()),
+        Command::Clean => cmd::clean(&args, &config).await,
         Command::Serve => cmd::serve(args, config).await,
-    };
-
-    if let Err(error) = result {
-        log::error!("{}", error);
     }
 }
@@ -0,0 +1,96 @@
+use crate::args::BuildMode;
+use crate::error::{Error, Result};
+use crate::staging::write_file;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::{Component, Path};
+use tokio::fs;
+
+/// Where the manifest is written, relative to `out_dir`
+pub const MANIFEST_FILE: &str = "sitegen_meta.toml";
+
+/// Describes a build in `out_dir`: the mode it was built in and every file it
+/// wrote, relative to `out_dir`. Files that aren't listed don't belong to
+/// sitegen and are never removed
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    mode: String,
+    outputs: BTreeSet<String>,
+}
+
+impl Manifest {
+    pub fn new(mode: BuildMode) -> Self {
+        Self {
+            mode: mode.to_string(),
+            outputs: BTreeSet::new(),
+        }
+    }
+
+    /// Loads the manifest of the previous build. A missing or unreadable
+    /// manifest is treated as empty, so nothing is pruned
+    pub async fn load(out_dir: &Path) -> Self {
+        let path = out_dir.join(MANIFEST_FILE);
+        match fs::read_to_string(path.as_path()).await {
+            Ok(contents) => toml::from_str(contents.as_str()).unwrap_or_else(|e| {
+                log::warn!("ignoring unreadable {:?}: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, out_dir: &Path) -> Result<()> {
+        let path = out_dir.join(MANIFEST_FILE);
+        let contents = toml::to_string_pretty(self).unwrap();
+        write_file(path.as_path(), contents)
+            .await
+            .map_err(Error::io(&path))
+    }
+
+    /// Records files written beneath `out_dir`
+    pub fn insert<I, P>(&mut self, out_dir: &Path, paths: I)
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            if let Ok(relative) = path.as_ref().strip_prefix(out_dir) {
+                self.outputs.insert(relative.to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    /// Removes the outputs of `previous` that this build no longer produces,
+    /// along with any directories that leaves empty
+    pub async fn prune(&self, out_dir: &Path, previous: &Manifest) -> Result<()> {
+        for stale in previous.outputs.difference(&self.outputs) {
+            let relative = Path::new(stale);
+            // Never follow a tampered manifest outside of `out_dir`
+            if !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                continue;
+            }
+
+            let path = out_dir.join(relative);
+            log::debug!("removing stale output {:?}", path);
+            match fs::remove_file(path.as_path()).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::Io(path, e)),
+            }
+
+            let mut dir = path.parent();
+            while let Some(parent) = dir.filter(|parent| *parent != out_dir) {
+                if fs::remove_dir(parent).await.is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+
+        Ok(())
+    }
+}
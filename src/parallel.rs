@@ -0,0 +1,103 @@
+use crate::error::{Error, Result};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use tokio::runtime::Handle;
+use tokio::task;
+
+/// How batches of independent files are compiled: how many at once, and
+/// whether a failure stops the build or is collected so the remaining files can
+/// still be compiled
+pub struct Jobs {
+    limit: usize,
+    keep_going: bool,
+    errors: Mutex<Vec<Error>>,
+}
+
+impl Jobs {
+    pub fn new(limit: usize, keep_going: bool) -> Self {
+        Self {
+            limit,
+            keep_going,
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Passes a result through, unless errors are being collected, in which
+    /// case a failure is recorded and `None` is returned in its place
+    pub fn collect<T>(&self, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.keep_going => {
+                self.errors.lock().unwrap().push(error);
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Every error collected since the last call
+    pub fn take_errors(&self) -> Vec<Error> {
+        self.errors.lock().unwrap().drain(..).collect()
+    }
+
+    /// Runs `task` for every item on up to `limit` threads at once, returning
+    /// the results in the order of `items`.
+    ///
+    /// Items are started in order. Unless errors are being collected, no new
+    /// items are started once one fails, and the error of the earliest failing
+    /// item is returned, which is the same error a sequential loop would have
+    /// stopped at. When collecting, failed items are left out of the results.
+    ///
+    /// Each task runs to completion on its own thread, so tasks may borrow
+    /// from the caller and don't need to be `Send`. Must be called from within
+    /// a multi-threaded runtime.
+    pub async fn try_map<T, R, F, Fut>(&self, items: Vec<T>, task: F) -> Result<Vec<R>>
+    where
+        T: Send,
+        R: Send,
+        F: Fn(T) -> Fut + Sync,
+        Fut: Future<Output = Result<R>>,
+    {
+        let count = items.len();
+        let threads = self.limit.clamp(1, count.max(1));
+        let handle = Handle::current();
+        let queue = Mutex::new(items.into_iter().enumerate());
+        let results = Mutex::new(Vec::with_capacity(count));
+        let failed = AtomicBool::new(false);
+
+        task::block_in_place(|| {
+            thread::scope(|scope| {
+                for _ in 0..threads {
+                    scope.spawn(|| {
+                        while !failed.load(Ordering::Relaxed) {
+                            let next = queue.lock().unwrap().next();
+                            let Some((index, item)) = next else {
+                                break;
+                            };
+
+                            let result = handle.block_on(task(item));
+                            if result.is_err() && !self.keep_going {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            results.lock().unwrap().push((index, result));
+                        }
+                    });
+                }
+            })
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _)| *index);
+
+        let mut values = Vec::with_capacity(count);
+        for (_, result) in results {
+            if let Some(value) = self.collect(result)? {
+                values.push(value);
+            }
+        }
+
+        Ok(values)
+    }
+}
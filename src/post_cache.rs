@@ -1,7 +1,28 @@
-use crate::compilers::FrontMatter;
+use crate::compilers::{collections, FrontMatter, Term};
+use crate::config::SortOrder;
+use crate::deps::normalize;
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Post {
+    pub slug: String,
+    pub permalink: String,
+    /// The same as `permalink`, under the name templates first used for it
+    pub url: String,
+    pub published: Option<DateTime<FixedOffset>>,
+    pub source: PathBuf,
+    pub content: String,
+    pub excerpt: String,
+    pub word_count: usize,
+    pub front_matter: FrontMatter,
+    pub taxonomies: BTreeMap<String, Vec<Term>>,
+}
 
 pub struct PostCache {
-    posts: Vec<FrontMatter>,
+    posts: Vec<Post>,
 }
 
 impl PostCache {
@@ -10,11 +31,39 @@ impl PostCache {
         Self { posts }
     }
 
-    pub fn add_ref(&mut self, post: FrontMatter) {
+    pub fn add_ref(&mut self, post: Post) {
+        self.posts.push(post);
+    }
+
+    /// Adds a post, replacing any previous version rendered from the same
+    /// source
+    pub fn replace(&mut self, post: Post) {
+        self.remove(post.source.clone());
         self.posts.push(post);
     }
 
-    pub fn posts(&self) -> &[FrontMatter] {
+    pub fn remove<P: AsRef<Path>>(&mut self, source: P) {
+        let source = normalize(source.as_ref());
+        self.posts.retain(|post| normalize(&post.source) != source);
+    }
+
+    /// Sorts posts by a dotted key into their serialized form, such as
+    /// `published` or `front_matter.title`. Posts without the key sort last
+    pub fn sort(&mut self, key: &str, order: SortOrder) {
+        let mut keyed = self
+            .posts
+            .drain(..)
+            .map(|post| {
+                let value = serde_json::to_value(&post).unwrap_or_default();
+                (collections::lookup(&value, key).cloned(), post)
+            })
+            .collect::<Vec<_>>();
+
+        keyed.sort_by(|(a, _), (b, _)| collections::compare(a.as_ref(), b.as_ref(), order));
+        self.posts = keyed.into_iter().map(|(_, post)| post).collect();
+    }
+
+    pub fn posts(&self) -> &[Post] {
         self.posts.as_slice()
     }
 }
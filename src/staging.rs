@@ -0,0 +1,204 @@
+use crate::config::BuildConfig;
+use crate::error::{Error, Result};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Builds happen in a copy of `out_dir` next to it, which is swapped into
+/// place only once the whole build succeeded. Readers of `out_dir` never see a
+/// half-written site, and a failed build leaves it untouched. On Linux the
+/// swap is a single atomic rename; elsewhere `out_dir` is briefly missing
+/// between two renames.
+///
+/// The copy is made of hard links, so preparing it costs one link per file
+/// rather than copying the whole site. Outputs must therefore be written with
+/// [`write_file`] and [`copy_file`], which replace a file instead of writing
+/// through the link into the published one.
+///
+/// The output replaced by a swap is kept as `<out_dir>.previous` when
+/// `build.keep_previous` is set, so that it can be rolled back to.
+pub struct Staging {
+    out_dir: PathBuf,
+    staging: String,
+    previous: PathBuf,
+    keep_previous: bool,
+}
+
+impl Staging {
+    pub fn new(config: &BuildConfig) -> Self {
+        let out_dir = config.out_dir.trim_end_matches(['/', '\\']);
+
+        Self {
+            out_dir: PathBuf::from(out_dir),
+            staging: format!("{}.staging", out_dir),
+            previous: previous_dir(config),
+            keep_previous: config.keep_previous.unwrap_or_default(),
+        }
+    }
+
+    /// Where the build writes its output
+    pub fn dir(&self) -> &str {
+        self.staging.as_str()
+    }
+
+    /// Starts staging from a copy of the current output, so that unchanged
+    /// outputs and files sitegen doesn't own carry over
+    pub async fn prepare(&self) -> Result<()> {
+        let staging = Path::new(self.dir());
+        remove_dir(staging).await?;
+
+        if fs::metadata(self.out_dir.as_path()).await.is_ok() {
+            link_dir(self.out_dir.as_path(), staging).await
+        } else {
+            fs::create_dir_all(staging)
+                .await
+                .map_err(Error::io(&self.staging))
+        }
+    }
+
+    /// Swaps the staged output into place
+    pub async fn publish(&self) -> Result<()> {
+        remove_dir(self.previous.as_path()).await?;
+
+        let staging = Path::new(self.dir());
+        let out_dir = self.out_dir.as_path();
+        if fs::metadata(out_dir).await.is_err() {
+            fs::rename(staging, out_dir)
+                .await
+                .map_err(Error::io(&self.staging))?;
+        } else if let Err(error) = exchange(staging, out_dir) {
+            log::debug!("unable to swap {:?} atomically: {}", out_dir, error);
+            fs::rename(out_dir, self.previous.as_path())
+                .await
+                .map_err(Error::io(out_dir))?;
+            fs::rename(staging, out_dir)
+                .await
+                .map_err(Error::io(&self.staging))?;
+        } else {
+            // Staging now holds the output that was replaced
+            fs::rename(staging, self.previous.as_path())
+                .await
+                .map_err(Error::io(&self.staging))?;
+        }
+
+        if !self.keep_previous {
+            remove_dir(self.previous.as_path()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Throws away the output of a failed build
+    pub async fn discard(&self) {
+        if let Err(error) = remove_dir(Path::new(self.dir())).await {
+            log::warn!("unable to remove staged output: {}", error);
+        }
+    }
+
+    /// Maps a path in the staging directory to where it ends up once published
+    pub fn published(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(self.dir()) {
+            Ok(relative) => self.out_dir.join(relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+}
+
+/// Where the output replaced by the last build is kept
+pub fn previous_dir(config: &BuildConfig) -> PathBuf {
+    let out_dir = config.out_dir.trim_end_matches(['/', '\\']);
+    PathBuf::from(format!("{}.previous", out_dir))
+}
+
+async fn remove_dir(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::Io(path.to_path_buf(), e)),
+        _ => Ok(()),
+    }
+}
+
+/// Writes an output file, replacing whatever was at `path`
+pub async fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    remove_file(path).await?;
+    fs::write(path, contents).await
+}
+
+/// Copies a file to an output path, replacing whatever was at `to`
+pub async fn copy_file(from: &Path, to: &Path) -> io::Result<u64> {
+    remove_file(to).await?;
+    fs::copy(from, to).await
+}
+
+async fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Swaps two paths in a single step
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid, NUL-terminated strings that outlive the
+    // call
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            a.as_ptr(),
+            libc::AT_FDCWD,
+            b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Mirrors `from` into `to` with hard links, copying files that can't be
+/// linked, e.g. because the directories are on different file systems
+async fn link_dir(from: &Path, to: &Path) -> Result<()> {
+    let mut dirs = vec![(from.to_path_buf(), to.to_path_buf())];
+
+    while let Some((from, to)) = dirs.pop() {
+        fs::create_dir_all(to.as_path())
+            .await
+            .map_err(Error::io(&to))?;
+
+        let mut entries = fs::read_dir(from.as_path())
+            .await
+            .map_err(Error::io(&from))?;
+        while let Some(entry) = entries.next_entry().await.map_err(Error::io(&from))? {
+            let path = entry.path();
+            let target = to.join(entry.file_name());
+            let file_type = entry.file_type().await.map_err(Error::io(&path))?;
+
+            if file_type.is_dir() {
+                dirs.push((path, target));
+            } else if fs::hard_link(path.as_path(), target.as_path())
+                .await
+                .is_err()
+            {
+                fs::copy(path.as_path(), target.as_path())
+                    .await
+                    .map_err(Error::io(&path))?;
+            }
+        }
+    }
+
+    Ok(())
+}